@@ -8,40 +8,27 @@ use plugin_api::prelude::*;
 struct CryptoPlugin;
 
 impl CryptoPlugin {
-    fn crypto(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn crypto(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
-        let text = match http_request_common::fetch_string_on_success(
+        let text = http_request_common::fetch_string_on_success(
             "https://api.coinmarketcap.com/v1/ticker/?limit=0",
             "",
-        ) {
-            Ok(text) => text,
-            Err(_) => {
-                ctx.send_channel("Fetch phail.");
-                return;
-            }
-        };
-        let json = match json::parse(&text) {
-            Ok(json) => json,
-            Err(_) => {
-                ctx.send_channel("Json fuckup.");
-                return;
-            }
-        };
+        )
+        .map_err(|e| CommandError::with_cause("Fetch phail", e))?;
+        let json = json::parse(&text).map_err(|e| CommandError::with_cause("Json fuckup", e))?;
         for entry in json.members() {
             if entry["id"] == &arg[..] {
                 let price_usd_obj = &entry["price_usd"];
-                let price_usd: f64 = match price_usd_obj.as_str().unwrap_or("").parse() {
-                    Ok(price) => price,
-                    Err(_) => {
-                        ctx.send_channel("Failed parsing price. Fuck it.");
-                        return;
-                    }
-                };
+                let price_usd: f64 =
+                    price_usd_obj.as_str().unwrap_or("").parse().map_err(|e| {
+                        CommandError::with_cause("Failed parsing price. Fuck it", e)
+                    })?;
                 ctx.send_channel(&format!("A {} is worth {} US dollars.", arg, price_usd));
-                return;
+                return Ok(());
             }
         }
         ctx.send_channel("Go make your own cryptocurrency");
+        Ok(())
     }
 }
 
@@ -10,7 +10,7 @@ struct IsoLangPlugin {
 }
 
 impl IsoLangPlugin {
-    fn isolang(this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn isolang(this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
         let this: &mut Self = this.downcast_mut().unwrap();
         let response = this
@@ -26,7 +26,8 @@ impl IsoLangPlugin {
                 None
             })
             .unwrap_or_else(|| "ISO MOTHERFUCKER, DO YOU SPEAK IT?".into());
-        ctx.send_channel(&format!("{}: {}", ctx.sender.nickname(), response));
+        ctx.reply(&response);
+        Ok(())
     }
 }
 
@@ -2,33 +2,51 @@
 extern crate plugin_api;
 
 use plugin_api::prelude::*;
+use plugin_api::toml;
+use std::error::Error;
 
-struct PermutPlugin;
+/// How long the reply may get before it's cut off, unless configured otherwise.
+const DEFAULT_MAX_LENGTH: usize = 700;
+
+struct PermutPlugin {
+    max_length: usize,
+}
 
 impl PermutPlugin {
-    fn permut(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn permut(this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
+        let max_length = this.downcast_ref::<Self>().unwrap().max_length;
         let perms = permutations(arg);
         let mut msg = String::new();
         for p in perms {
             msg += &(p + ", ");
-            if msg.len() > 700 {
-                let cut = String::from_utf8_lossy(&msg.as_bytes()[..700]);
-                ctx.send_channel(&format!(
-                    "{}: {}",
-                    ctx.sender.nickname(),
-                    &format!("{}...", cut)
-                ));
-                return;
+            if msg.len() > max_length {
+                let cut = String::from_utf8_lossy(&msg.as_bytes()[..max_length]);
+                ctx.reply(&format!("{}...", cut));
+                return Ok(());
             }
         }
-        ctx.send_channel(&format!("{}: {}", ctx.sender.nickname(), &msg));
+        ctx.reply(&msg);
+        Ok(())
     }
 }
 
 impl Plugin for PermutPlugin {
     fn new() -> Self {
-        PermutPlugin
+        Self {
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+    fn configure(&mut self, cfg: &toml::Value) -> Result<(), Box<Error>> {
+        self.max_length = match cfg.get("max-length") {
+            Some(value) => value
+                .as_integer()
+                .filter(|&len| len > 0)
+                .ok_or("max-length must be a positive integer")?
+                as usize,
+            None => DEFAULT_MAX_LENGTH,
+        };
+        Ok(())
     }
     fn register(&self, meta: &mut PluginMeta) {
         meta.add_simple_command("permut", "permutate shit", Self::permut);
@@ -35,63 +35,49 @@ pub fn parse_first_result(body: &str) -> Result<Option<String>, Box<Error>> {
 struct SearchPlugin;
 
 impl SearchPlugin {
-    fn search(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn search(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
         if arg.is_empty() {
             ctx.send_channel("You need to search for something bro.");
-            return;
+            return Ok(());
         }
-        match fetch_string("https://www.bing.com/search?q=", arg) {
-            Ok((body, status)) => {
-                if status.is_success() {
-                    match parse_first_result(&body) {
-                        Ok(Some(result)) => {
-                            ctx.send_channel(&result);
-                            let title = get_title(&result);
-                            ctx.send_channel(&title);
-                        }
-                        Ok(None) => {
-                            ctx.send_channel("BING-FU MOTHERFUCKER, DO YOU KNOW IT?");
-                        }
-                        Err(e) => {
-                            ctx.send_channel(&format!("Error: {}", e));
-                        }
-                    }
-                } else {
-                    ctx.send_channel(&format!("HTTP status: {}", status));
-                }
+        let (body, status) = fetch_string("https://www.bing.com/search?q=", arg)
+            .map_err(|e| CommandError::with_cause("Error when searching", e))?;
+        if !status.is_success() {
+            return Err(format!("HTTP status: {}", status).into());
+        }
+        match parse_first_result(&body)? {
+            Some(result) => {
+                ctx.send_channel(&result);
+                let title = get_title(&result);
+                ctx.send_channel(&title);
             }
-            Err(e) => {
-                ctx.send_channel(&format!("Error when searching: {}", e));
+            None => {
+                ctx.send_channel("BING-FU MOTHERFUCKER, DO YOU KNOW IT?");
             }
         }
+        Ok(())
     }
-    fn ytsearch(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn ytsearch(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
         if arg.is_empty() {
             ctx.send_channel("FLAVA FLAVA FOR MY PEOPLE PEOPLE, COME ON KID, HERE COMES THE FINAL");
-            return;
+            return Ok(());
         }
-        match fetch_string("https://www.youtube.com/results?search_query=", arg) {
-            Ok((body, status)) => {
-                if status.is_success() {
-                    match extract_yt(&body) {
-                        Ok(link) => {
-                            let mut ytlink = format!("https://www.youtube.com/watch?v={}", link);
-                            // Stupid &amp;
-                            ytlink = ytlink.replace("&amp;", "&");
-                            ctx.send_channel(&ytlink);
-                            let title = get_title(&ytlink);
-                            ctx.send_channel(&title);
-                        }
-                        Err(e) => ctx.send_channel(&format!("Error extracting: {}", e)),
-                    }
-                } else {
-                    ctx.send_channel(&format!("HTTP status: {}", status))
-                }
-            }
-            Err(e) => ctx.send_channel(&format!("Error when yting: {}", e)),
+        let (body, status) = fetch_string("https://www.youtube.com/results?search_query=", arg)
+            .map_err(|e| CommandError::with_cause("Error when yting", e))?;
+        if !status.is_success() {
+            return Err(format!("HTTP status: {}", status).into());
         }
+        let link =
+            extract_yt(&body).map_err(|e| CommandError::with_cause("Error extracting", e))?;
+        let mut ytlink = format!("https://www.youtube.com/watch?v={}", link);
+        // Stupid &amp;
+        ytlink = ytlink.replace("&amp;", "&");
+        ctx.send_channel(&ytlink);
+        let title = get_title(&ytlink);
+        ctx.send_channel(&title);
+        Ok(())
     }
 }
 
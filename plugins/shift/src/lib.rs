@@ -6,13 +6,15 @@ use plugin_api::prelude::*;
 struct ShiftPlugin;
 
 impl ShiftPlugin {
-    fn shl(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn shl(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
-        ctx.send_channel(&format!("{}: {}", ctx.sender.nickname(), &shl(arg)));
+        ctx.reply(&shl(arg));
+        Ok(())
     }
-    fn shr(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn shr(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
-        ctx.send_channel(&format!("{}: {}", ctx.sender.nickname(), &shr(arg)));
+        ctx.reply(&shr(arg));
+        Ok(())
     }
 }
 
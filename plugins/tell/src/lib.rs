@@ -4,66 +4,93 @@ extern crate split_whitespace_rest;
 
 use plugin_api::prelude::*;
 use split_whitespace_rest::SplitWhitespace;
-use std::collections::HashMap;
 
-struct Message {
-    sender: String,
-    content: String,
-}
-
-struct TellPlugin {
-    messages: HashMap<String, Message>,
-}
+/// Messages are kept in the plugin storage, keyed by the recipient's lowercase nick.
+/// The value is the sender and the content, separated by a tab.
+struct TellPlugin;
 
 impl TellPlugin {
-    fn tell(this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn tell(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
-        let this: &mut TellPlugin = this.downcast_mut().unwrap();
 
         let mut sw = SplitWhitespace::new(arg);
 
         match sw.next() {
             Some(to) => {
-                let msg = sw.rest_as_slice();
+                let msg = sw.rest_as_slice().trim_start();
                 if msg.is_empty() {
                     ctx.send_channel("NEED A MESSAGE.");
+                    return Ok(());
                 }
-                let msg = Message {
-                    sender: (*ctx.sender.nickname()).clone(),
-                    content: msg.to_owned(),
-                };
-                this.messages.insert(to.to_owned(), msg);
-                ctx.send_channel(&format!(
-                    "{}: I'll pass that on to {}",
-                    ctx.sender.nickname(),
-                    to
-                ));
+                let value = format!("{}\t{}", ctx.sender, msg);
+                ctx.storage
+                    .put(&to.to_lowercase(), value.as_bytes())
+                    .map_err(|e| CommandError::with_cause("Failed to save the message", e))?;
+                ctx.reply(&format!("I'll pass that on to {}", to));
             }
             None => ctx.send_channel("NEED A RECIPIENT."),
         }
+        Ok(())
+    }
+    /// Deliver the message left for the sender of `ctx`, if any.
+    fn deliver(ctx: Context) {
+        let nick = &ctx.sender.to_lowercase();
+        if let Some(value) = ctx.storage.get(nick) {
+            let value = String::from_utf8_lossy(&value);
+            let mut fields = value.splitn(2, '\t');
+            let sender = fields.next().unwrap_or("");
+            let content = fields.next().unwrap_or("");
+            let _ = ctx.storage.delete(nick);
+            ctx.reply(&format!("<{}>: {}", sender, content));
+        }
     }
 }
 
 impl Plugin for TellPlugin {
     fn new() -> Self {
-        Self {
-            messages: HashMap::new(),
-        }
+        TellPlugin
     }
     fn register(&self, meta: &mut PluginMeta) {
         meta.add_simple_command("tell", "Leave a message for someone", Self::tell);
     }
     fn channel_msg(&mut self, _msg: &str, ctx: Context) {
-        let nick = ctx.sender.nickname();
-        let mut remove = None;
-        if let Some(msg) = self.messages.get(&*nick) {
-            ctx.send_channel(&format!("{}: <{}>: {}", nick, msg.sender, msg.content));
-            remove = Some(&*nick);
+        Self::deliver(ctx);
+    }
+    fn user_join(&mut self, ctx: Context) {
+        Self::deliver(ctx);
+    }
+    fn nick_change(&mut self, old: &str, new: &str, ctx: NetworkContext) {
+        let (old, new) = (&old.to_lowercase(), &new.to_lowercase());
+        if old == new {
+            return;
         }
-        if let Some(remove) = remove {
-            self.messages.remove(remove);
+        if let Some(value) = ctx.storage.get(old) {
+            if ctx.storage.put(new, &value).is_ok() {
+                let _ = ctx.storage.delete(old);
+            }
         }
     }
 }
 
 plugin_export!(TellPlugin);
+
+#[test]
+fn test_tell() {
+    use plugin_api::testing::Harness;
+    let mut harness = Harness::<TellPlugin>::new();
+    let sent = harness.invoke("tell bob hi there", "alice").unwrap();
+    assert_eq!(sent[0].text, "alice: I'll pass that on to bob");
+    let sent = harness.channel_msg("hello", "bob");
+    assert_eq!(sent[0].text, "bob: <alice>: hi there");
+    assert!(harness.channel_msg("hello again", "bob").is_empty());
+    // Command names are case insensitive, and the arguments can be left out
+    let sent = harness.invoke("TELL", "alice").unwrap();
+    assert_eq!(sent[0].text, "NEED A RECIPIENT.");
+    let sent = harness.invoke("tell bob", "alice").unwrap();
+    assert_eq!(sent[0].text, "NEED A MESSAGE.");
+    assert!(harness.channel_msg("hello", "bob").is_empty());
+    // Nicks are case insensitive too
+    harness.invoke("tell Bob hi", "alice").unwrap();
+    let sent = harness.channel_msg("hello", "bob");
+    assert_eq!(sent[0].text, "bob: <alice>: hi");
+}
@@ -17,7 +17,7 @@ pub fn query(query: &str) -> Result<String, Box<Error>> {
 struct UdPlugin;
 
 impl UdPlugin {
-    fn ud(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn ud(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let contains = opts.get_or_empty("contains");
         let fuck = opts.get_or_empty("fuck");
         let term = opts.free.join(" ");
@@ -26,77 +26,67 @@ impl UdPlugin {
             .get(0)
             .map(|arg| arg.parse::<u8>().unwrap_or(0))
             .unwrap_or(0);
-        with_json(&term, ctx, |json| {
-            let mut i = 0;
-            let entries = &json["list"];
-            for v in entries.members() {
-                if !opts.given("loose") {
-                    if v["word"].as_str().unwrap().to_lowercase() != term.to_lowercase() {
-                        continue;
-                    }
+        if term.is_empty() {
+            ctx.send_channel("You need to search for something bro.");
+            return Ok(());
+        }
+        let json = fetch_json(&term)?;
+        let mut i = 0;
+        let entries = &json["list"];
+        for v in entries.members() {
+            if !opts.given("loose") {
+                if v["word"].as_str().unwrap().to_lowercase() != term.to_lowercase() {
+                    continue;
                 }
-                if let Some(def) = v["definition"].as_str() {
-                    let mut all_contains_satisfied = true;
-                    for c in contains {
-                        if !def.to_lowercase().contains(&c.to_lowercase())
-                            && !v["example"]
-                                .as_str()
-                                .unwrap_or("")
-                                .to_lowercase()
-                                .contains(&c.to_lowercase())
-                        {
-                            all_contains_satisfied = false;
-                        }
+            }
+            if let Some(def) = v["definition"].as_str() {
+                let mut all_contains_satisfied = true;
+                for c in contains {
+                    if !def.to_lowercase().contains(&c.to_lowercase())
+                        && !v["example"]
+                            .as_str()
+                            .unwrap_or("")
+                            .to_lowercase()
+                            .contains(&c.to_lowercase())
+                    {
+                        all_contains_satisfied = false;
                     }
-                    let mut any_contains_fuck = false;
-                    for f in fuck {
-                        if def.to_lowercase().contains(&f.to_lowercase())
-                            || v["example"]
-                                .as_str()
-                                .unwrap_or("")
-                                .to_lowercase()
-                                .contains(&f.to_lowercase())
-                        {
-                            any_contains_fuck = true;
-                        }
+                }
+                let mut any_contains_fuck = false;
+                for f in fuck {
+                    if def.to_lowercase().contains(&f.to_lowercase())
+                        || v["example"]
+                            .as_str()
+                            .unwrap_or("")
+                            .to_lowercase()
+                            .contains(&f.to_lowercase())
+                    {
+                        any_contains_fuck = true;
                     }
+                }
 
-                    if all_contains_satisfied && !any_contains_fuck {
-                        if i == n {
-                            display_def(
-                                &format!("{}: {}", v["word"].as_str().unwrap_or("?"), def),
-                                v["example"].as_str(),
-                                &term,
-                                ctx,
-                            );
-                            return;
-                        }
-                        i += 1;
+                if all_contains_satisfied && !any_contains_fuck {
+                    if i == n {
+                        display_def(
+                            &format!("{}: {}", v["word"].as_str().unwrap_or("?"), def),
+                            v["example"].as_str(),
+                            &term,
+                            ctx,
+                        );
+                        return Ok(());
                     }
+                    i += 1;
                 }
             }
-            ctx.send_channel("ENGLISH MOTHERFUCKER, DO YOU SPEAK IT?");
-        });
+        }
+        ctx.send_channel("ENGLISH MOTHERFUCKER, DO YOU SPEAK IT?");
+        Ok(())
     }
 }
 
-fn with_json<F: Fn(JsonValue)>(arg: &str, ctx: Context, fun: F) {
-    if arg.is_empty() {
-        ctx.send_channel("You need to search for something bro.");
-        return;
-    }
-    match query(arg) {
-        Ok(body) => match json::parse(&body) {
-            Ok(json) => fun(json),
-            Err(e) => {
-                ctx.send_channel(&format!("Phailed parsing json ({})", e));
-                return;
-            }
-        },
-        Err(e) => {
-            ctx.send_channel(&format!("Error when uding: {}", e));
-        }
-    }
+fn fetch_json(arg: &str) -> Result<JsonValue, CommandError> {
+    let body = query(arg).map_err(|e| CommandError::with_cause("Error when uding", e))?;
+    json::parse(&body).map_err(|e| CommandError::with_cause("Phailed parsing json", e))
 }
 
 fn display_def(mut def: &str, example: Option<&str>, arg: &str, ctx: Context) {
@@ -26,79 +26,62 @@ fn query_wp(what: &str) -> Result<String, Box<Error>> {
     fetch_string_on_success(base, &what)
 }
 
-fn process_wp_result(result: Result<String, Box<Error>>, article_name: &str, ctx: Context) {
-    match result {
-        Ok(body) => {
-            let json = match json::parse(&body) {
-                Ok(json) => json,
-                Err(e) => {
-                    ctx.send_channel(&format!("Phailed parsing json ({})", e));
-                    return;
-                }
-            };
-            let pages = &json["query"]["pages"];
-            // Just grab first page
-            let page = match pages.entries().nth(0) {
-                Some((_k, v)) => v,
-                None => {
-                    ctx.send_channel("No wiki page found.");
-                    return;
-                }
-            };
-            match page["extract"].as_str() {
-                Some(extract) => {
-                    for line in extract.lines() {
-                        ctx.send_channel(line);
-                    }
-                    let encoded = wikiencode(article_name);
-                    let url = format!("https://en.wikipedia.org/wiki/{}", encoded);
-
-                    ctx.send_channel(&url);
-                }
-                None => {
-                    ctx.send_channel(
-                        "YOU BETRAYED ME, OPENSEARCH. HOW COULD YOU DARE? HOW COULD YOU DAAAARE!?",
-                    );
-                    return;
-                }
-            }
+fn process_wp_result(
+    result: Result<String, Box<Error>>,
+    article_name: &str,
+    ctx: Context,
+) -> CommandResult {
+    let body = result.map_err(|e| CommandError::with_cause("Error when wikiing", e))?;
+    let json =
+        json::parse(&body).map_err(|e| CommandError::with_cause("Phailed parsing json", e))?;
+    let pages = &json["query"]["pages"];
+    // Just grab first page
+    let page = match pages.entries().nth(0) {
+        Some((_k, v)) => v,
+        None => {
+            ctx.send_channel("No wiki page found.");
+            return Ok(());
         }
-        Err(e) => {
-            ctx.send_channel(&format!("Error when wikiing: {}", e));
+    };
+    match page["extract"].as_str() {
+        Some(extract) => {
+            for line in extract.lines() {
+                ctx.send_channel(line);
+            }
+            let encoded = wikiencode(article_name);
+            let url = format!("https://en.wikipedia.org/wiki/{}", encoded);
+
+            ctx.send_channel(&url);
+            Ok(())
         }
+        None => Err(CommandError::new(
+            "YOU BETRAYED ME, OPENSEARCH. HOW COULD YOU DARE? HOW COULD YOU DAAAARE!?",
+        )),
     }
 }
 
 struct WPlugin;
 
 impl WPlugin {
-    fn w(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) {
+    fn w(_this: &mut Plugin, opts: ParsedOpts, ctx: Context) -> CommandResult {
         let arg = &opts.free.join(" ");
         if arg.is_empty() {
             ctx.send_channel("You need to search for something bro.");
-            return;
+            return Ok(());
         }
-        match query_opensearch(arg) {
-            Ok(body) => {
-                let json = match json::parse(&body) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        ctx.send_channel(&format!("Phailed parsing json ({})", e));
-                        return;
-                    }
-                };
-                match json[1][0].as_str() {
-                    Some(name) => {
-                        let wp_result = query_wp(name);
-                        process_wp_result(wp_result, name, ctx);
-                    }
-                    None => {
-                        ctx.send_channel(r#"¯\_(ツ)_/¯"#);
-                        return;
-                    }
-                }
+        let body =
+            query_opensearch(arg).map_err(|e| CommandError::with_cause("Error when wikiing", e))?;
+        let json =
+            json::parse(&body).map_err(|e| CommandError::with_cause("Phailed parsing json", e))?;
+        match json[1][0].as_str() {
+            Some(name) => {
+                let wp_result = query_wp(name);
+                process_wp_result(wp_result, name, ctx)
+            }
+            None => {
+                ctx.send_channel(r#"¯\_(ツ)_/¯"#);
+                Ok(())
             }
-            Err(e) => ctx.send_channel(&format!("Error when wikiing: {}", e)),
         }
     }
 }
@@ -0,0 +1,133 @@
+//! Administrative commands, available both through IPC and IRC.
+
+use crate::config::{self, Config};
+use crate::core::Core;
+
+/// Execute an admin command, returning the reply to it.
+pub(crate) fn execute(command_str: &str, core: &mut Core, config: &mut Config) -> String {
+    use std::fmt::Write;
+
+    let mut words = command_str.split(' ');
+    let mut reply = String::new();
+    match words.next().unwrap() {
+        "quit" => {
+            core.irc_bridge.request_quit(words.next());
+            core.quit = true;
+        }
+        "say" => match words.next() {
+            Some(channel) => {
+                let msg = words.collect::<Vec<_>>().join(" ");
+                core.irc_bridge.msg(channel, &msg);
+            }
+            None => writeln!(&mut reply, "Need channel, buddy.").unwrap(),
+        },
+        "load" => match words.next() {
+            Some(name) => match core.load_plugin(name, config) {
+                Ok(()) => {
+                    writeln!(&mut reply, "Loaded \"{}\" plugin.", name).unwrap();
+                    core.irc_bridge
+                        .msg_all_joined_channels(&format!("[Plugin '{}' was loaded]", name));
+                }
+                Err(e) => {
+                    writeln!(&mut reply, "Failed to load \"{}\": {}", name, e).unwrap();
+                }
+            },
+            None => writeln!(&mut reply, "Name, please!").unwrap(),
+        },
+        "unload" => match words.next() {
+            Some(name) => {
+                if core.unload_plugin(name) {
+                    writeln!(&mut reply, "Removed \"{}\" plugin.", name).unwrap();
+                    core.irc_bridge
+                        .msg_all_joined_channels(&format!("[Plugin '{}' was unloaded]", name));
+                }
+            }
+            None => writeln!(&mut reply, "Don't forget the name!").unwrap(),
+        },
+        "reload" => match words.next() {
+            Some(name) => match core.reload_plugin(name, config) {
+                Ok(()) => {
+                    writeln!(&mut reply, "Reloaded plugin {}", name).unwrap();
+                    core.irc_bridge
+                        .msg_all_joined_channels(&format!("[Plugin '{}' was reloaded]", name));
+                }
+                Err(e) => writeln!(&mut reply, "Failed to reload plugin {}: {}", name, e).unwrap(),
+            },
+            None => writeln!(&mut reply, "Need a name, faggot").unwrap(),
+        },
+        "reload-cfg" => match config::load() {
+            Ok(mut cfg) => {
+                core.apply_plugin_overrides(&mut cfg);
+                for (name, e) in core.reconfigure_plugins(config, &cfg) {
+                    writeln!(&mut reply, "Failed to reconfigure \"{}\": {}", name, e).unwrap();
+                }
+                *config = cfg;
+            }
+            Err(e) => writeln!(&mut reply, "{}", e).unwrap(),
+        },
+        "status" => {
+            writeln!(&mut reply, "Connection: {}", core.irc_bridge.state).unwrap();
+            writeln!(&mut reply, "Nick: {}", core.nick()).unwrap();
+            reply.push_str(&core.workers.status());
+            writeln!(
+                &mut reply,
+                "Queued messages: {}",
+                core.irc_bridge.outbox.len()
+            )
+            .unwrap();
+        }
+        "errors" => {
+            for detail in core.command_errors.lock().unwrap().iter() {
+                writeln!(&mut reply, "{}", detail).unwrap();
+            }
+        }
+        cmd @ "enable" | cmd @ "disable" => match (words.next(), words.next()) {
+            (Some(plugin), Some(channel)) => {
+                let enabled = cmd == "enable";
+                match core.set_plugin_enabled(config, plugin, channel, enabled) {
+                    Ok(()) => {
+                        writeln!(&mut reply, "Plugin \"{}\" {}d in {}.", plugin, cmd, channel)
+                            .unwrap()
+                    }
+                    Err(e) => {
+                        writeln!(&mut reply, "Failed to {} \"{}\": {}", cmd, plugin, e).unwrap()
+                    }
+                }
+            }
+            _ => writeln!(&mut reply, "Usage: {} <plugin> <channel>", cmd).unwrap(),
+        },
+        "ignore" => match words.next() {
+            Some(pattern) => match core.ignore(pattern) {
+                Ok(()) => writeln!(&mut reply, "Ignoring {}.", pattern).unwrap(),
+                Err(e) => writeln!(&mut reply, "Failed to ignore {}: {}", pattern, e).unwrap(),
+            },
+            None => writeln!(&mut reply, "Ignore who?").unwrap(),
+        },
+        "unignore" => match words.next() {
+            Some(pattern) => match core.unignore(pattern) {
+                Ok(true) => writeln!(&mut reply, "No longer ignoring {}.", pattern).unwrap(),
+                Ok(false) => writeln!(&mut reply, "{} wasn't ignored.", pattern).unwrap(),
+                Err(e) => writeln!(&mut reply, "Failed to unignore {}: {}", pattern, e).unwrap(),
+            },
+            None => writeln!(&mut reply, "Unignore who?").unwrap(),
+        },
+        "ignores" => {
+            let list = core.ignore_list();
+            if list.is_empty() {
+                writeln!(&mut reply, "Nobody is ignored.").unwrap();
+            } else {
+                writeln!(&mut reply, "Ignored: {}", list.join(", ")).unwrap();
+            }
+        }
+        "join" => match words.next() {
+            Some(name) => core.irc_bridge.join(name),
+            None => writeln!(&mut reply, "Need a channel name to join").unwrap(),
+        },
+        "leave" => match words.next() {
+            Some(name) => core.irc_bridge.leave(name),
+            None => writeln!(&mut reply, "Need a channel name to leave").unwrap(),
+        },
+        _ => writeln!(&mut reply, "Unknown command, bro.").unwrap(),
+    }
+    reply
+}
@@ -0,0 +1,192 @@
+//! Capability negotiation, and authentication through SASL or NickServ.
+
+use crate::config::{self, Sasl};
+use hiirc::{Code, Message};
+use std::time::{Duration, Instant};
+
+/// Starts capability negotiation. It's sent before registering, so SASL can happen in time.
+pub(crate) const CAP_LS: &str = "CAP LS 302\r\n";
+/// Capabilities that are requested if the server has them, besides `sasl`.
+const CAPABILITIES: &[&str] = &["account-notify", "extended-join"];
+/// How long to wait for NickServ before joining the channels anyway.
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+/// The longest AUTHENTICATE payload that fits in one message.
+const AUTHENTICATE_CHUNK_LEN: usize = 400;
+
+/// The authentication state of a connection.
+#[derive(Default)]
+pub(crate) struct Auth {
+    /// Capabilities offered by the server, while they're being listed.
+    offered: Vec<String>,
+    logged_in: bool,
+    /// Whether NickServ was asked to identify us.
+    identifying: bool,
+    /// Since when the channels are waiting to be joined.
+    join_pending: Option<Instant>,
+}
+
+impl Auth {
+    /// Handle a message from the server, and return the lines to reply with.
+    pub fn handle(&mut self, msg: &Message, config: &config::Auth, nick: &str) -> Vec<String> {
+        match msg.code {
+            Code::Unknown(ref cmd) if cmd == "CAP" => self.negotiate(msg, config),
+            Code::Unknown(ref cmd) if cmd == "AUTHENTICATE" && trailing(msg) == "+" => {
+                authenticate(config, nick)
+            }
+            Code::RplLoggedin => {
+                self.logged_in = true;
+                Vec::new()
+            }
+            Code::RplSaslsuccess => vec!["CAP END".into()],
+            Code::ErrNicklocked
+            | Code::ErrSaslfail
+            | Code::ErrSasltoolong
+            | Code::ErrSaslaborted
+            | Code::ErrSaslalready => {
+                eprintln!("SASL authentication failed: {}", trailing(msg));
+                vec!["CAP END".into()]
+            }
+            _ => Vec::new(),
+        }
+    }
+    /// Registration is complete. Returns the lines to send.
+    pub fn welcome(&mut self, config: &config::Auth, nick: &str, now: Instant) -> Vec<String> {
+        self.join_pending = Some(now);
+        if self.logged_in || !config.nickserv {
+            return Vec::new();
+        }
+        self.identifying = true;
+        vec![format!(
+            "PRIVMSG NickServ :IDENTIFY {} {}",
+            account(config, nick),
+            config.account_password
+        )]
+    }
+    /// Whether the channels should be joined now. It's true once per connection.
+    pub fn take_join(&mut self, config: &config::Auth, now: Instant) -> bool {
+        let since = match self.join_pending {
+            Some(since) => since,
+            None => return false,
+        };
+        if config.wait_before_join && !self.logged_in {
+            if self.identifying && now.duration_since(since) < IDENTIFY_TIMEOUT {
+                return false;
+            }
+            eprintln!("Not logged in. Joining the channels anyway.");
+        }
+        self.join_pending = None;
+        true
+    }
+    fn negotiate(&mut self, msg: &Message, config: &config::Auth) -> Vec<String> {
+        let caps = trailing(msg).split_whitespace();
+        match msg.args.get(1).map(String::as_str) {
+            Some("LS") => {
+                // Values like in `sasl=PLAIN,EXTERNAL` don't matter
+                self.offered
+                    .extend(caps.map(|cap| cap.split('=').next().unwrap().to_owned()));
+                // More is coming
+                if msg.args.get(2).map_or(false, |arg| arg == "*") {
+                    return Vec::new();
+                }
+                let wanted: Vec<&str> = CAPABILITIES
+                    .iter()
+                    .cloned()
+                    .chain(config.sasl.map(|_| "sasl"))
+                    .filter(|&cap| self.offered.iter().any(|c| c == cap))
+                    .collect();
+                self.offered.clear();
+                if wanted.is_empty() {
+                    vec!["CAP END".into()]
+                } else {
+                    vec![format!("CAP REQ :{}", wanted.join(" "))]
+                }
+            }
+            Some("ACK") => match config.sasl {
+                Some(Sasl::Plain) if caps.clone().any(|cap| cap == "sasl") => {
+                    vec!["AUTHENTICATE PLAIN".into()]
+                }
+                Some(Sasl::External) if caps.clone().any(|cap| cap == "sasl") => {
+                    vec!["AUTHENTICATE EXTERNAL".into()]
+                }
+                _ => vec!["CAP END".into()],
+            },
+            Some("NAK") => vec!["CAP END".into()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The services account to log into.
+fn account<'a>(config: &'a config::Auth, nick: &'a str) -> &'a str {
+    config.account.as_ref().map_or(nick, |account| account)
+}
+
+/// Reply to the server being ready for our credentials.
+fn authenticate(config: &config::Auth, nick: &str) -> Vec<String> {
+    match config.sasl {
+        Some(Sasl::Plain) => {
+            let account = account(config, nick);
+            let credentials = format!("{}\0{}\0{}", account, account, config.account_password);
+            let payload = base64::encode(credentials.as_bytes());
+            let mut lines: Vec<String> = payload
+                .as_bytes()
+                .chunks(AUTHENTICATE_CHUNK_LEN)
+                .map(|chunk| format!("AUTHENTICATE {}", String::from_utf8_lossy(chunk)))
+                .collect();
+            // A full chunk means that more is coming, unless we say otherwise
+            if payload.len() % AUTHENTICATE_CHUNK_LEN == 0 {
+                lines.push("AUTHENTICATE +".into());
+            }
+            lines
+        }
+        // The certificate is all the server needs
+        Some(Sasl::External) => vec!["AUTHENTICATE +".into()],
+        None => vec!["AUTHENTICATE *".into()],
+    }
+}
+
+/// The last parameter of a message.
+fn trailing(msg: &Message) -> &str {
+    msg.suffix
+        .as_ref()
+        .or_else(|| msg.args.last())
+        .map_or("", |s| s)
+}
+
+#[test]
+fn test_sasl_plain() {
+    let msg = |code: &str, args: &[&str], suffix: Option<&str>| Message {
+        prefix: None,
+        code: Code::Unknown(code.into()),
+        args: args.iter().map(|&arg| arg.to_owned()).collect(),
+        suffix: suffix.map(str::to_owned),
+    };
+    let mut config = config::Auth::default();
+    config.sasl = Some(Sasl::Plain);
+    config.account_password = "hunter2".into();
+    config.wait_before_join = true;
+    let mut auth = Auth::default();
+    let ls = msg(
+        "CAP",
+        &["*", "LS", "*"],
+        Some("multi-prefix sasl=PLAIN,EXTERNAL"),
+    );
+    assert!(auth.handle(&ls, &config, "bonca").is_empty());
+    let ls = msg("CAP", &["*", "LS"], Some("extended-join"));
+    assert_eq!(
+        auth.handle(&ls, &config, "bonca"),
+        ["CAP REQ :extended-join sasl"]
+    );
+    let ack = msg("CAP", &["*", "ACK"], Some("extended-join sasl"));
+    assert_eq!(auth.handle(&ack, &config, "bonca"), ["AUTHENTICATE PLAIN"]);
+    let plus = msg("AUTHENTICATE", &["+"], None);
+    assert_eq!(
+        auth.handle(&plus, &config, "bonca"),
+        ["AUTHENTICATE Ym9uY2EAYm9uY2EAaHVudGVyMg=="]
+    );
+    let now = Instant::now();
+    auth.welcome(&config, "bonca", now);
+    // SASL didn't work out, and there's nothing else to wait for
+    assert!(auth.take_join(&config, now));
+    assert!(!auth.take_join(&config, now));
+}
@@ -7,11 +7,46 @@ use std::io::prelude::*;
 use toml;
 
 #[derive(Deserialize)]
-pub struct Plugin {}
+pub struct Plugin {
+    /// Explicit path of the plugin library.
+    pub path: Option<String>,
+    /// Plugin specific settings. They are handed to the plugin as they are.
+    #[serde(flatten)]
+    pub settings: toml::value::Table,
+}
 
 #[derive(Deserialize)]
 pub struct Server {
     pub url: String,
+    /// Defaults to 6697 with TLS, and 6667 without.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: bool,
+    /// Whether the certificate of the server is checked when connecting over TLS.
+    #[serde(rename = "verify-certificate", default = "default_verify_certificate")]
+    pub verify_certificate: bool,
+    /// PEM file of an additional certificate authority to trust, e.g. a self-signed one.
+    #[serde(rename = "ca-file")]
+    pub ca_file: Option<String>,
+    /// PKCS #12 file with the client certificate to authenticate with (CertFP).
+    #[serde(rename = "client-cert")]
+    pub client_cert: Option<String>,
+    #[serde(rename = "client-cert-password", default)]
+    pub client_cert_password: String,
+}
+
+impl Server {
+    pub fn port(&self) -> u16 {
+        match self.port {
+            Some(port) => port,
+            None if self.tls => 6697,
+            None => 6667,
+        }
+    }
+}
+
+fn default_verify_certificate() -> bool {
+    true
 }
 
 #[derive(Deserialize)]
@@ -20,15 +55,291 @@ pub struct Bot {
     pub channels: Vec<String>,
     #[serde(rename = "command-prefix")]
     pub cmd_prefix: String,
+    /// Directories to look for plugin libraries in.
+    #[serde(rename = "plugin-dirs", default)]
+    pub plugin_dirs: Vec<String>,
+    /// Directory where the persistent storage of plugins is kept.
+    #[serde(rename = "storage-dir", default = "default_storage_dir")]
+    pub storage_dir: String,
+    /// Whether to tell users when their command fails.
+    #[serde(rename = "report-errors", default = "default_report_errors")]
+    pub report_errors: bool,
+    /// Nicks to use when `nick` is taken, in order.
+    #[serde(rename = "alternate-nicks", default)]
+    pub alternate_nicks: Vec<String>,
+    /// Seconds between attempts to get `nick` back while using another one. 0 means never.
+    #[serde(
+        rename = "reclaim-nick-interval",
+        default = "default_reclaim_nick_interval"
+    )]
+    pub reclaim_nick_interval: u64,
+    /// NickServ command for getting `nick` back from whoever has it.
+    #[serde(rename = "reclaim-nick-with")]
+    pub reclaim_nick_with: Option<Reclaim>,
+}
+
+/// NickServ commands for getting a nick back.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Reclaim {
+    /// Disconnect whoever has the nick, then change to it.
+    Ghost,
+    /// Take the nick over directly.
+    Regain,
+}
+
+fn default_storage_dir() -> String {
+    "storage".into()
+}
+
+fn default_report_errors() -> bool {
+    true
+}
+
+fn default_reclaim_nick_interval() -> u64 {
+    300
+}
+
+/// Settings that only apply to a single channel.
+#[derive(Deserialize, Default)]
+pub struct Channel {
+    /// Command prefix used in this channel instead of the global one.
+    #[serde(rename = "command-prefix")]
+    pub cmd_prefix: Option<String>,
+    /// If set, only these plugins are enabled in this channel.
+    #[serde(rename = "enabled-plugins")]
+    pub enabled_plugins: Option<Vec<String>>,
+    /// Plugins that are disabled in this channel.
+    #[serde(rename = "disabled-plugins", default)]
+    pub disabled_plugins: Vec<String>,
+    /// Overrides `quiet` of the suggestions settings in this channel.
+    #[serde(rename = "quiet-unknown-commands")]
+    pub quiet_unknown_commands: Option<bool>,
+    /// Whether to offer running the suggested command when an unknown command is used.
+    #[serde(rename = "offer-suggestions", default)]
+    pub offer_suggestions: bool,
+}
+
+impl Channel {
+    /// Whether `plugin` is enabled in this channel.
+    pub fn plugin_enabled(&self, plugin: &str) -> bool {
+        let allowed = match self.enabled_plugins {
+            Some(ref enabled) => enabled.iter().any(|p| p == plugin),
+            None => true,
+        };
+        allowed && !self.disabled_plugins.iter().any(|p| p == plugin)
+    }
+    /// Enable or disable `plugin` in this channel.
+    pub fn set_plugin_enabled(&mut self, plugin: &str, enabled: bool) {
+        self.disabled_plugins.retain(|p| p != plugin);
+        if enabled {
+            if let Some(ref mut allowed) = self.enabled_plugins {
+                if !allowed.iter().any(|p| p == plugin) {
+                    allowed.push(plugin.to_owned());
+                }
+            }
+        } else {
+            self.disabled_plugins.push(plugin.to_owned());
+        }
+    }
+}
+
+/// Who gets which role. Entries are `nick!user@host` masks with `*` and `?` wildcards,
+/// or services account names prefixed with `$a:`. Accounts need `extended-join`,
+/// `account-notify` and WHOX from the server.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Roles {
+    pub owner: Vec<String>,
+    pub admin: Vec<String>,
+    pub trusted: Vec<String>,
+}
+
+/// At most `commands` commands in `seconds` seconds.
+#[derive(Deserialize, Clone, Copy)]
+pub struct Rate {
+    pub commands: usize,
+    pub seconds: u64,
+}
+
+/// Limits on how often commands can be used.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Limits {
+    /// Rate limit for each user.
+    pub user: Option<Rate>,
+    /// Rate limit for each channel.
+    pub channel: Option<Rate>,
+    /// Seconds a user has to wait before using a command again, per command.
+    pub cooldowns: HashMap<String, u64>,
+}
+
+/// The pool of threads that plugins run on.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Workers {
+    /// How many plugin calls can run at the same time.
+    pub threads: usize,
+    /// How many calls of one plugin can wait while it is busy, or for a free thread.
+    /// Any more are dropped.
+    #[serde(rename = "max-queue")]
+    pub max_queue: usize,
+    /// How long a command can run before the user is told that it timed out.
+    #[serde(rename = "command-timeout-secs")]
+    pub command_timeout_secs: u64,
+}
+
+impl Default for Workers {
+    fn default() -> Self {
+        Self {
+            threads: 8,
+            max_queue: 100,
+            command_timeout_secs: 30,
+        }
+    }
+}
+
+/// Suggestions for unknown commands.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Suggestions {
+    /// Don't reply to unknown commands at all.
+    pub quiet: bool,
+    /// At most this many commands are suggested.
+    pub max: usize,
+    /// How different a suggested command can be from the unknown one, relative to its length.
+    #[serde(rename = "max-distance")]
+    pub max_distance: f64,
+}
+
+impl Default for Suggestions {
+    fn default() -> Self {
+        Self {
+            quiet: false,
+            max: 3,
+            max_distance: 0.34,
+        }
+    }
+}
+
+/// SASL mechanisms.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sasl {
+    /// Account name and password.
+    Plain,
+    /// The client certificate of the TLS connection.
+    External,
+}
+
+/// Authentication to the server and to services.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Auth {
+    /// Password of the server itself (PASS).
+    #[serde(rename = "server-password")]
+    pub server_password: Option<String>,
+    pub sasl: Option<Sasl>,
+    /// Services account to log into. Defaults to the nick.
+    pub account: Option<String>,
+    #[serde(rename = "account-password")]
+    pub account_password: String,
+    /// Identify to NickServ if not logged in through SASL.
+    pub nickserv: bool,
+    /// Don't join the channels before logging in.
+    #[serde(rename = "wait-before-join")]
+    pub wait_before_join: bool,
+}
+
+/// Flood control of outgoing messages.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Flood {
+    /// How many messages can be sent in a row before the rate limit kicks in.
+    pub burst: u32,
+    /// After the burst, one message can be sent per this many milliseconds.
+    #[serde(rename = "interval-ms")]
+    pub interval_ms: u64,
+    /// How many messages can be waiting to be sent to a single channel or user.
+    /// Any more are dropped.
+    #[serde(rename = "max-queue")]
+    pub max_queue: usize,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Self {
+            burst: 5,
+            interval_ms: 1000,
+            max_queue: 20,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: Server,
     pub bot: Bot,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub flood: Flood,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub workers: Workers,
+    #[serde(default)]
+    pub suggestions: Suggestions,
+    /// Alternative names for commands, possibly with some arguments baked in.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub roles: Roles,
+    /// Per-channel settings.
+    #[serde(default)]
+    pub channels: HashMap<String, Channel>,
     pub plugins: HashMap<String, Plugin>,
 }
 
+impl Config {
+    /// The settings of `channel`, if it has any.
+    pub fn channel(&self, channel: &str) -> Option<&Channel> {
+        self.channels
+            .iter()
+            .find(|&(name, _)| name.eq_ignore_ascii_case(channel))
+            .map(|(_, settings)| settings)
+    }
+    /// The settings of `channel`, created if it has none yet.
+    pub fn channel_mut(&mut self, channel: &str) -> &mut Channel {
+        let name = self
+            .channels
+            .keys()
+            .find(|name| name.eq_ignore_ascii_case(channel))
+            .cloned()
+            .unwrap_or_else(|| channel.to_owned());
+        self.channels.entry(name).or_insert_with(Channel::default)
+    }
+    /// Whether `plugin` is enabled in `target`, which is a channel or a nick.
+    pub fn plugin_enabled(&self, target: &str, plugin: &str) -> bool {
+        self.channel(target)
+            .map_or(true, |ch| ch.plugin_enabled(plugin))
+    }
+    /// The command prefix used in `target`, which is a channel or a nick.
+    pub fn cmd_prefix(&self, target: &str) -> &str {
+        self.channel(target)
+            .and_then(|ch| ch.cmd_prefix.as_ref())
+            .unwrap_or(&self.bot.cmd_prefix)
+    }
+    /// The settings of a plugin as a TOML table. Empty if the plugin has no section.
+    pub fn plugin_settings(&self, name: &str) -> toml::Value {
+        let table = match self.plugins.get(name) {
+            Some(plugin) => plugin.settings.clone(),
+            None => toml::value::Table::new(),
+        };
+        toml::Value::Table(table)
+    }
+}
+
 pub const PATH: &str = "boncarobot.toml";
 
 fn load_file_to_string() -> Result<String, io::Error> {
@@ -41,6 +352,26 @@ fn load_file_to_string() -> Result<String, io::Error> {
 
 pub fn load() -> Result<Config, Box<Error>> {
     let text = load_file_to_string()?;
-    let config = toml::from_str(&text)?;
+    let mut config: Config = toml::from_str(&text)?;
+    // Commands are case insensitive, so aliases are looked up by their lowercase name
+    config.aliases = config
+        .aliases
+        .drain()
+        .map(|(alias, command)| (alias.to_lowercase(), command))
+        .collect();
     Ok(config)
 }
+
+#[test]
+fn test_channel_plugins() {
+    let mut ch = Channel::default();
+    assert!(ch.plugin_enabled("linktitle"));
+    ch.set_plugin_enabled("linktitle", false);
+    assert!(!ch.plugin_enabled("linktitle"));
+    ch.set_plugin_enabled("linktitle", true);
+    assert!(ch.plugin_enabled("linktitle"));
+    ch.enabled_plugins = Some(vec!["tell".into()]);
+    assert!(!ch.plugin_enabled("linktitle"));
+    ch.set_plugin_enabled("linktitle", true);
+    assert!(ch.plugin_enabled("linktitle"));
+}
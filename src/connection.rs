@@ -0,0 +1,144 @@
+//! Keeping the bot connected to the server.
+
+use crate::core::SharedCore;
+use crate::proxy::Proxy;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long to wait before the first reconnection attempt.
+const MIN_DELAY: Duration = Duration::from_secs(2);
+/// The delay doubles with every failed attempt, up to this.
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// State of the connection to the server.
+pub(crate) enum State {
+    Connecting {
+        attempt: u32,
+    },
+    /// Registered with the server.
+    Connected {
+        since: Instant,
+    },
+    Disconnected {
+        reason: String,
+        retry_at: Instant,
+    },
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Connecting { attempt: 1 }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Connecting { attempt } => write!(f, "connecting (attempt {})", attempt),
+            State::Connected { since } => {
+                write!(f, "connected for {}s", since.elapsed().as_secs())
+            }
+            State::Disconnected {
+                ref reason,
+                retry_at,
+            } => {
+                let left = retry_at.saturating_duration_since(Instant::now());
+                write!(
+                    f,
+                    "disconnected ({}), retrying in {}s",
+                    reason,
+                    left.as_secs()
+                )
+            }
+        }
+    }
+}
+
+/// Exponentially growing delays between reconnection attempts.
+struct Backoff {
+    failures: u32,
+}
+
+impl Backoff {
+    /// The delay before the next attempt. `jitter` is between 0 and 1, and spreads the delays
+    /// so that bots that got disconnected together don't all come back at once.
+    fn next(&mut self, jitter: f64) -> Duration {
+        let factor = 2u32.saturating_pow(self.failures);
+        let delay = MIN_DELAY
+            .checked_mul(factor)
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+        self.failures += 1;
+        delay.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// Connect to `server`, and reconnect whenever the connection is lost, until the bot quits.
+pub(crate) fn run(core: &SharedCore, proxy: &Proxy, nick: &str, password: Option<&str>) {
+    let mut backoff = Backoff { failures: 0 };
+    loop {
+        core.lock().irc_bridge.state = State::Connecting {
+            attempt: backoff.failures + 1,
+        };
+        let reason = match proxy.open() {
+            Ok(server) => {
+                let server = server.to_string();
+                let mut settings = hiirc::Settings::new(&server, nick);
+                if let Some(password) = password {
+                    settings = settings.password(password);
+                }
+                match settings.dispatch(core.clone()) {
+                    Ok(()) => "connection closed".to_owned(),
+                    Err(e) => format!("{:?}", e),
+                }
+            }
+            Err(e) => format!("proxy failed: {}", e),
+        };
+        let delay = {
+            let mut core = core.lock();
+            let was_connected = match core.irc_bridge.state {
+                State::Connected { .. } => true,
+                _ => false,
+            };
+            core.disconnected();
+            if core.quit {
+                return;
+            }
+            if was_connected {
+                backoff.failures = 0;
+            }
+            let delay = backoff.next(jitter());
+            eprintln!(
+                "Disconnected: {}. Reconnecting in {}s.",
+                reason,
+                delay.as_secs()
+            );
+            core.irc_bridge.state = State::Disconnected {
+                reason,
+                retry_at: Instant::now() + delay,
+            };
+            delay
+        };
+        thread::sleep(delay);
+    }
+}
+
+/// A number between 0 and 1 that's random enough for spreading out reconnections.
+pub(crate) fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    f64::from(nanos % 1000) / 1000.0
+}
+
+#[test]
+fn test_backoff() {
+    let mut backoff = Backoff { failures: 0 };
+    assert_eq!(backoff.next(1.0), MIN_DELAY);
+    assert_eq!(backoff.next(1.0), MIN_DELAY * 2);
+    assert_eq!(backoff.next(0.0), MIN_DELAY * 2);
+    for _ in 0..40 {
+        assert!(backoff.next(1.0) <= MAX_DELAY);
+    }
+    assert_eq!(backoff.next(0.0), MAX_DELAY / 2);
+}
@@ -0,0 +1,63 @@
+//! Console mode: talk to the bot through stdin and stdout instead of IRC.
+
+use crate::core::SharedCore;
+use plugin_api::MessageKind;
+use std::io::{self, prelude::*};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The pseudo-channel the console user talks in.
+const CHANNEL: &str = "#console";
+/// When the input ends, wait for replies until the bot has been quiet for this long.
+const LINGER: Duration = Duration::from_secs(3);
+
+/// Read lines from stdin as if `nick` said them in the console channel, and print what the bot
+/// says in return.
+pub(crate) fn run(core: &SharedCore, nick: &str, bot_nick: &str) {
+    let last_output = Arc::new(Mutex::new(Instant::now()));
+    let outbox = {
+        let mut core = core.lock();
+        core.irc_bridge.init_console();
+        Arc::clone(&core.irc_bridge.outbox)
+    };
+    thread::spawn({
+        let bot_nick = bot_nick.to_owned();
+        let last_output = Arc::clone(&last_output);
+        move || loop {
+            let msg = outbox.next();
+            let to = if msg.target == CHANNEL {
+                String::new()
+            } else {
+                format!("[{}] ", msg.target)
+            };
+            match msg.kind {
+                MessageKind::Privmsg => println!("{}<{}> {}", to, bot_nick, msg.text),
+                MessageKind::Notice => println!("{}-{}- {}", to, bot_nick, msg.text),
+                MessageKind::Action => println!("{}* {} {}", to, bot_nick, msg.text),
+            }
+            *last_output.lock().unwrap() = Instant::now();
+        }
+    });
+    eprintln!("Talking in {} as {}. Ctrl+D quits.", CHANNEL, nick);
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                break;
+            }
+        };
+        let mut core = core.lock();
+        core.channel_msg(CHANNEL, nick, &line);
+        if core.quit {
+            return;
+        }
+    }
+    // Commands run on their own threads, so give them a chance to reply
+    *last_output.lock().unwrap() = Instant::now();
+    while last_output.lock().unwrap().elapsed() < LINGER {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -1,13 +1,29 @@
+use crate::admin;
+use crate::auth::Auth;
 use crate::config::Config;
-use crate::plugin_container::PluginContainer;
-use distance::damerau_levenshtein;
+use crate::connection;
+use crate::event::Event;
+use crate::limits::RateLimiter;
+use crate::nick::Nick;
+use crate::outbox::{Outbox, Outgoing};
+use crate::pipeline::{self, Stage, StageError};
+use crate::plugin_container::{self, lock_plugin, try_lock_plugin, PluginContainer};
+use crate::suggest;
+use crate::users::Users;
+use crate::workers::Workers;
 use hiirc::{Channel, ChannelUser, Irc, IrcWrite, Listener};
-use plugin_api::Context;
+use plugin_api::optparse;
+use plugin_api::storage::Storage;
+use plugin_api::{CommandError, Context, MessageKind, NetworkContext, Plugin, Role, Sink, Target};
 use split_whitespace_rest::SplitWhitespace;
 use std;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 /// The core of the bot.
 ///
@@ -18,100 +34,357 @@ use std::sync::{Arc, Mutex, MutexGuard};
 pub(crate) struct Core {
     config: Arc<Mutex<Config>>,
     plugins: HashMap<String, PluginContainer>,
+    /// Timers of the loaded plugins that are waiting to fire.
+    timers: Vec<ScheduledTimer>,
+    /// Where the persistent storage of plugins is kept.
+    storage_dir: PathBuf,
+    /// The storages of the plugins, by name. They are kept across reloads.
+    storages: HashMap<String, Storage>,
+    /// Hostmasks and accounts of the users, for checking their roles.
+    users: Users,
+    /// Capability negotiation and logging into services.
+    auth: Auth,
+    nick: Nick,
+    /// Nicks and hostmasks whose messages are ignored. The values are empty.
+    ignored: Storage,
+    /// Plugins that were enabled or disabled in a channel by an admin, keyed by
+    /// `channel\tplugin`. The values are `1` for enabled and `0` for disabled.
+    plugin_overrides: Storage,
+    rate_limiter: RateLimiter,
+    /// Plugin code runs on these.
+    pub workers: Workers,
+    /// Corrected commands that were offered to be run, by channel and lowercase nick.
+    offered_commands: HashMap<(String, String), (String, Instant)>,
     pub irc_bridge: IrcBridge,
+    /// Details of the most recent command failures, for IPC clients.
+    pub command_errors: Arc<Mutex<VecDeque<String>>>,
     /// This is true if for some reason, the core deemed that boncarobot should quit
     pub quit: bool,
 }
 
 /// Allows IRC access (send messages/join/leave/quit/etc.) for IPC clients.
 pub(crate) struct IrcBridge {
-    /// IRC handle. It has delayed initialization, but can be assumed to be always `Some` after
-    /// the initialization.
+    /// IRC handle. It's `None` while the bot isn't connected.
     handle: Option<Arc<Irc>>,
+    /// Whether the bot runs in console mode, without an IRC connection.
+    console: bool,
+    pub state: connection::State,
+    /// Channels the bot is in, or joins once it's connected. They're rejoined on reconnect.
+    channels: Vec<String>,
+    /// Every message the bot says goes through here.
+    pub outbox: Arc<Outbox>,
 }
 
 impl IrcBridge {
-    fn new() -> Self {
-        Self { handle: None }
+    fn new(outbox: Outbox) -> Self {
+        Self {
+            handle: None,
+            console: false,
+            state: connection::State::default(),
+            channels: Vec::new(),
+            outbox: Arc::new(outbox),
+        }
     }
     fn init(&mut self, irc: Arc<Irc>) {
         self.handle = Some(irc);
+        self.outbox.set_connected(true);
+        self.state = connection::State::Connected {
+            since: Instant::now(),
+        };
+    }
+    /// Run without IRC. The contents of the outbox are printed by the console instead.
+    pub fn init_console(&mut self) {
+        self.console = true;
+        self.outbox.set_connected(true);
+    }
+    /// Whether we can talk to anyone yet.
+    fn is_ready(&self) -> bool {
+        self.handle.is_some() || self.console
     }
     pub fn request_quit(&self, msg: Option<&str>) {
-        self.handle.as_ref().unwrap().quit(msg).unwrap();
+        if let Some(ref irc) = self.handle {
+            let _ = irc.quit(msg);
+        }
     }
+    /// Queue a message to be sent.
     pub fn msg(&self, target: &str, text: &str) {
-        self.handle.as_ref().unwrap().privmsg(target, text).unwrap();
+        self.outbox.send_chunked(MessageKind::Privmsg, target, text);
+    }
+    /// Actually send a message from the outbox. Returns false if it couldn't be sent.
+    pub fn write(&self, msg: &Outgoing) -> bool {
+        let irc = match self.handle {
+            Some(ref irc) => irc,
+            None => return false,
+        };
+        let result = match msg.kind {
+            MessageKind::Privmsg => irc.privmsg(&msg.target, &msg.text),
+            MessageKind::Notice => irc.notice(&msg.target, &msg.text),
+            MessageKind::Action => {
+                irc.privmsg(&msg.target, &format!("\x01ACTION {}\x01", msg.text))
+            }
+        };
+        result.is_ok()
     }
     pub fn msg_all_joined_channels(&self, text: &str) {
-        for channel in self.handle.as_ref().unwrap().channels() {
-            self.msg(channel.name(), text);
+        if self.handle.is_none() {
+            return;
+        }
+        for channel in &self.channels {
+            self.msg(channel, text);
+        }
+    }
+    /// Join `channel` now, or once connected.
+    pub fn join(&mut self, channel: &str) {
+        match self.handle {
+            // It's remembered when the server confirms the join
+            Some(ref irc) => {
+                let _ = irc.join(channel, None);
+            }
+            None => self.joined(channel),
         }
     }
-    pub fn join(&self, channel: &str) {
-        self.handle.as_ref().unwrap().join(channel, None).unwrap();
+    pub fn leave(&mut self, channel: &str) {
+        match self.handle {
+            Some(ref irc) => {
+                let _ = irc.part(channel, None);
+            }
+            None => self.left(channel),
+        }
     }
-    pub fn leave(&self, channel: &str) {
-        self.handle.as_ref().unwrap().part(channel, None).unwrap();
+    fn joined(&mut self, channel: &str) {
+        if !self
+            .channels
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(channel))
+        {
+            self.channels.push(channel.to_owned());
+        }
+    }
+    fn left(&mut self, channel: &str) {
+        self.channels.retain(|c| !c.eq_ignore_ascii_case(channel));
+    }
+    /// Keep track of which channels the bot is in.
+    fn observe(&mut self, event: &Event, nick: &str) {
+        match *event {
+            Event::Join {
+                ref channel,
+                nick: ref joiner,
+            } if joiner.eq_ignore_ascii_case(nick) => self.joined(channel),
+            Event::Part {
+                ref channel,
+                nick: ref parter,
+                ..
+            } if parter.eq_ignore_ascii_case(nick) => self.left(channel),
+            Event::Kick {
+                ref channel,
+                ref kicked,
+                ..
+            } if kicked.eq_ignore_ascii_case(nick) => self.left(channel),
+            _ => {}
+        }
     }
 }
 
 impl Core {
     pub fn new(config: Arc<Mutex<Config>>) -> Self {
-        // Load plugins
-        let mut plugins = HashMap::new();
-        {
+        let (storage_dir, outbox, workers, nick) = {
             let cfg = config.lock().unwrap();
+            (
+                PathBuf::from(&cfg.bot.storage_dir),
+                Outbox::new(&cfg.flood),
+                Workers::new(&cfg.workers),
+                Nick::new(&cfg.bot.nick),
+            )
+        };
+        let core_dir = storage_dir.join(CORE_STORAGE_DIR);
+        let ignored = Storage::open(&core_dir, IGNORE_LIST_NAMESPACE)
+            .unwrap_or_else(|e| panic!("Failed to open the ignore list: {}", e));
+        let plugin_overrides = Storage::open(&core_dir, PLUGIN_OVERRIDES_NAMESPACE)
+            .unwrap_or_else(|e| panic!("Failed to open the plugin overrides: {}", e));
+        let mut core = Self {
+            config: Arc::clone(&config),
+            plugins: HashMap::new(),
+            timers: Vec::new(),
+            storage_dir,
+            storages: HashMap::new(),
+            users: Users::default(),
+            auth: Auth::default(),
+            nick,
+            ignored,
+            plugin_overrides,
+            rate_limiter: RateLimiter::default(),
+            workers,
+            offered_commands: HashMap::new(),
+            irc_bridge: IrcBridge::new(outbox),
+            command_errors: Arc::new(Mutex::new(VecDeque::new())),
+            quit: false,
+        };
 
+        // Load plugins
+        {
+            let mut cfg = config.lock().unwrap();
+            core.apply_plugin_overrides(&mut cfg);
             for k in cfg.plugins.keys() {
-                plugins.insert(k.clone(), PluginContainer::load(k).unwrap());
+                let pc = core
+                    .plugin_storage(k)
+                    .map_err(Box::from)
+                    .and_then(|storage| PluginContainer::load(k, &cfg, storage))
+                    .unwrap_or_else(|e| panic!("{}", e));
+                core.insert_plugin(k, pc);
             }
         }
 
-        Self {
-            config,
-            plugins,
-            irc_bridge: IrcBridge::new(),
-            quit: false,
+        core
+    }
+    pub fn channel_msg(&mut self, channel: &str, sender: &str, message: &str) {
+        self.handle_message(&ReplyTarget::Channel(channel.to_owned()), sender, message);
+    }
+    fn private_msg(&mut self, sender: &str, message: &str) {
+        self.handle_message(&ReplyTarget::User(sender.to_owned()), sender, message);
+    }
+    fn handle_message(&mut self, target: &ReplyTarget, sender: &str, message: &str) {
+        if self.is_ignored(sender) {
+            return;
+        }
+        let prefix = self
+            .config
+            .lock()
+            .unwrap()
+            .cmd_prefix(target.name())
+            .to_owned();
+        if is_valid_command(message, &prefix) && !self.within_rate_limits(target, sender) {
+            eprintln!("Rate limited command from {}: {}", sender, message);
+            return;
+        }
+        if message.trim() == format!("{}{}", prefix, ACCEPT_OFFER) {
+            if let Some(command) = self.take_offered_command(target, sender) {
+                self.handle_command(target, sender, &command);
+                return;
+            }
+        }
+        if !self.handle_help(&prefix, target, sender, message) {
+            self.delegate_to_plugins(&prefix, target, sender, message);
         }
     }
-    fn channel_msg(
-        &mut self,
-        irc: &Arc<Irc>,
-        channel: &Arc<Channel>,
-        sender: &Arc<ChannelUser>,
-        message: &str,
-    ) {
-        let prefix = self.config.lock().unwrap().bot.cmd_prefix.clone();
-        if !self.handle_help(&prefix, irc, channel, sender, message) {
-            self.delegate_to_plugins(&prefix, irc, channel, sender, message);
+    /// Whether messages from `nick` are ignored.
+    fn is_ignored(&self, nick: &str) -> bool {
+        self.ignored
+            .scan_prefix("")
+            .iter()
+            .any(|&(ref pattern, _)| self.users.matches(nick, pattern))
+    }
+    /// Ignore messages from users matching `pattern`, which is a nick or a hostmask.
+    pub fn ignore(&self, pattern: &str) -> io::Result<()> {
+        self.ignored.put(&pattern.to_lowercase(), &[])
+    }
+    /// Stop ignoring `pattern`. Returns whether it was ignored.
+    pub fn unignore(&self, pattern: &str) -> io::Result<bool> {
+        self.ignored.delete(&pattern.to_lowercase())
+    }
+    /// Enable or disable `plugin` in `channel`, and remember it across configuration reloads.
+    pub fn set_plugin_enabled(
+        &self,
+        config: &mut Config,
+        plugin: &str,
+        channel: &str,
+        enabled: bool,
+    ) -> io::Result<()> {
+        let key = format!("{}\t{}", channel.to_lowercase(), plugin);
+        self.plugin_overrides
+            .put(&key, if enabled { b"1" } else { b"0" })?;
+        config
+            .channel_mut(channel)
+            .set_plugin_enabled(plugin, enabled);
+        Ok(())
+    }
+    /// Apply the plugins enabled and disabled by admins on top of the configuration.
+    pub fn apply_plugin_overrides(&self, config: &mut Config) {
+        for (key, value) in self.plugin_overrides.scan_prefix("") {
+            let mut fields = key.splitn(2, '\t');
+            if let (Some(channel), Some(plugin)) = (fields.next(), fields.next()) {
+                config
+                    .channel_mut(channel)
+                    .set_plugin_enabled(plugin, value == b"1");
+            }
         }
     }
+    /// The nicks and hostmasks that are ignored.
+    pub fn ignore_list(&self) -> Vec<String> {
+        self.ignored
+            .scan_prefix("")
+            .into_iter()
+            .map(|(pattern, _)| pattern)
+            .collect()
+    }
+    /// The command that was offered to `sender` in `target`, if it's still valid.
+    fn take_offered_command(&mut self, target: &ReplyTarget, sender: &str) -> Option<String> {
+        let key = (target.name().to_owned(), sender.to_lowercase());
+        match self.offered_commands.remove(&key) {
+            Some((command, offered)) if offered.elapsed() < OFFER_TIMEOUT => Some(command),
+            _ => None,
+        }
+    }
+    /// Record a command invocation, unless it goes over the rate limits.
+    fn within_rate_limits(&mut self, target: &ReplyTarget, sender: &str) -> bool {
+        let channel = match *target {
+            ReplyTarget::Channel(ref name) => Some(&name[..]),
+            ReplyTarget::User(_) => None,
+        };
+        let config = self.config.lock().unwrap();
+        self.rate_limiter
+            .allow(&config.limits, sender, channel, Instant::now())
+    }
     /// Recognize and handle the help command. Returns whether the command we looked at was
     /// the help command.
     fn handle_help(
         &mut self,
         prefix: &str,
-        irc: &Irc,
-        channel: &Channel,
-        sender: &ChannelUser,
+        target: &ReplyTarget,
+        sender: &str,
         message: &str,
     ) -> bool {
         use std::fmt::Write;
         let help_string = format!("{}help", prefix);
 
         if message.starts_with(&help_string) {
+            let aliases = self.config.lock().unwrap().aliases.clone();
+            let disabled = self.disabled_plugins(target);
             if let Some(arg) = message[help_string.len()..].split_whitespace().next() {
-                for plugin in self.plugins.values() {
+                let expanded = expand_alias(&aliases, arg);
+                if expanded != arg {
+                    self.irc_bridge.msg(
+                        target.name(),
+                        &format!("{}: {} is an alias for '{}'", sender, arg, expanded),
+                    );
+                }
+                let arg = expanded.split_whitespace().next().unwrap_or("");
+                if arg == ADMIN_COMMAND {
+                    self.irc_bridge.msg(
+                        target.name(),
+                        &format!(
+                            "{}: {} <load|unload|reload|enable|disable|join|leave|say|\
+                             reload-cfg|ignore|unignore|ignores|status|errors|quit> [args] (requires {})",
+                            sender,
+                            ADMIN_COMMAND,
+                            Role::Admin
+                        ),
+                    );
+                    return true;
+                }
+                for (name, plugin) in &self.plugins {
+                    if disabled.contains(name) {
+                        continue;
+                    }
                     for cmd in &plugin.meta.commands {
                         if cmd.name == arg {
-                            let _ = irc.privmsg(
-                                channel.name(),
-                                &format!("{}: {}", sender.nickname(), cmd.help),
-                            );
+                            let help = match cmd.role {
+                                Role::Everyone => format!("{}: {}", sender, cmd.help),
+                                role => format!("{}: {} (requires {})", sender, cmd.help, role),
+                            };
+                            self.irc_bridge.msg(target.name(), &help);
                             for opt in &cmd.opts {
-                                let _ = irc.privmsg(
-                                    channel.name(),
+                                self.irc_bridge.msg(
+                                    target.name(),
                                     &format!("-{} --{} {}", opt.short, opt.long, opt.help),
                                 );
                             }
@@ -126,12 +399,24 @@ impl Core {
                 "The following commands are available ({} <command>): ",
                 &help_string
             );
-            for plugin in self.plugins.values() {
+            for (name, plugin) in &self.plugins {
+                if disabled.contains(name) {
+                    continue;
+                }
                 for cmd in &plugin.meta.commands {
                     let _ = write!(&mut msg, "{}, ", cmd.name);
                 }
             }
-            let _ = irc.privmsg(channel.name(), &format!("{}: {}", sender.nickname(), msg));
+            if !aliases.is_empty() {
+                let mut names: Vec<&String> = aliases.keys().collect();
+                names.sort();
+                let _ = write!(&mut msg, "aliases: ");
+                for name in names {
+                    let _ = write!(&mut msg, "{}, ", name);
+                }
+            }
+            self.irc_bridge
+                .msg(target.name(), &format!("{}: {}", sender, msg));
             return true;
         }
         false
@@ -139,114 +424,743 @@ impl Core {
     fn delegate_to_plugins(
         &mut self,
         command_prefix: &str,
-        irc: &Arc<Irc>,
-        channel: &Arc<Channel>,
-        sender: &Arc<ChannelUser>,
+        target: &ReplyTarget,
+        sender: &str,
         message: &str,
     ) {
         if is_valid_command(message, command_prefix) {
-            self.handle_command(irc, channel, sender, &message[command_prefix.len()..]);
+            self.handle_command(target, sender, &message[command_prefix.len()..]);
         }
-        self.delegate_non_command(irc, channel, sender, message);
+        self.delegate_non_command(target, sender, message);
     }
-    fn handle_command(
-        &mut self,
-        irc: &Arc<Irc>,
-        channel: &Arc<Channel>,
-        sender: &Arc<ChannelUser>,
-        command: &str,
-    ) {
-        let mut sw = SplitWhitespace::new(command);
-        let command = match sw.next() {
-            Some(command) => command,
-            None => return,
+    fn handle_command(&mut self, target: &ReplyTarget, sender: &str, command: &str) {
+        let (aliases, report_errors, role, suggestions, prefix) = {
+            let config = self.config.lock().unwrap();
+            (
+                config.aliases.clone(),
+                config.bot.report_errors,
+                self.users.role(sender, &config.roles),
+                config.suggestions.clone(),
+                config.cmd_prefix(target.name()).to_owned(),
+            )
         };
-        let command = &command.to_lowercase();
-        let arg = sw.rest_as_slice();
-        let mut match_found = false;
-        let mut closest_match = ("", usize::max_value());
-        for plugin in self.plugins.values_mut() {
-            for cmd in &plugin.meta.commands {
-                if command == cmd.name {
-                    match_found = true;
-                    let commandline = arg.trim_start().to_owned();
-                    match plugin_api::optparse::parse(&commandline, &cmd.opts) {
-                        Ok(parsed_opts) => {
-                            std::thread::spawn({
-                                let plugin = plugin.plugin.clone();
-                                let irc = Arc::clone(irc);
-                                let channel = Arc::clone(channel);
-                                let sender = Arc::clone(sender);
-
-                                let fun = cmd.fun;
-                                move || {
-                                    fun(
-                                        &mut *plugin.lock().unwrap(),
-                                        parsed_opts,
-                                        Context::new(&irc, &channel, &sender),
-                                    );
-                                }
-                            });
-                        }
-                        Err(e) => {
-                            let _ = irc.privmsg(channel.name(), &format!("{:?}", e));
+        {
+            let expanded = expand_alias(&aliases, command);
+            let mut sw = SplitWhitespace::new(&expanded);
+            if let Some(name) = sw.next() {
+                if name.eq_ignore_ascii_case(ADMIN_COMMAND) {
+                    self.handle_admin(target, sender, role, sw.rest_as_slice());
+                    return;
+                }
+            }
+        }
+        let commands = pipeline::split(command);
+        if commands.len() > pipeline::MAX_STAGES {
+            self.irc_bridge.msg(
+                target.name(),
+                &format!(
+                    "Sorry {}, a pipeline can have at most {} commands.",
+                    sender,
+                    pipeline::MAX_STAGES
+                ),
+            );
+            return;
+        }
+        let disabled = self.disabled_plugins(target);
+        let mut stages = Vec::new();
+        for (i, command) in commands.iter().enumerate() {
+            let command = expand_alias(&aliases, command);
+            match self.resolve_stage(&command, target, sender, role, &disabled) {
+                Ok(stage) => stages.push(stage),
+                Err(Unresolved::Reported) => return,
+                Err(Unresolved::Unknown(name, suggested)) => {
+                    let mut msg = suggest::describe(&name, &suggested);
+                    let (quiet, offer) = {
+                        let config = self.config.lock().unwrap();
+                        match config.channel(target.name()) {
+                            Some(ch) => (
+                                ch.quiet_unknown_commands.unwrap_or(suggestions.quiet),
+                                ch.offer_suggestions,
+                            ),
+                            None => (suggestions.quiet, false),
                         }
+                    };
+                    if quiet {
+                        return;
+                    }
+                    if let (true, Some(best)) = (offer, suggested.first()) {
+                        let mut corrected: Vec<String> =
+                            commands.iter().map(|c| c.to_string()).collect();
+                        corrected[i] = suggest::correct(&command, best);
+                        let corrected = corrected.join(" | ");
+                        msg.push_str(&format!(
+                            " Say '{}{}' to run '{}'.",
+                            prefix, ACCEPT_OFFER, corrected
+                        ));
+                        let now = Instant::now();
+                        self.offered_commands
+                            .retain(|_, &mut (_, offered)| now - offered < OFFER_TIMEOUT);
+                        self.offered_commands.insert(
+                            (target.name().to_owned(), sender.to_lowercase()),
+                            (corrected, now),
+                        );
+                    }
+                    self.irc_bridge.msg(target.name(), &msg);
+                    return;
+                }
+            }
+        }
+        {
+            let config = self.config.lock().unwrap();
+            let now = Instant::now();
+            for stage in &stages {
+                let cooldown = self
+                    .rate_limiter
+                    .cooldown(&config.limits, sender, stage.name, now);
+                if let Some(left) = cooldown {
+                    self.irc_bridge.outbox.send_chunked(
+                        MessageKind::Notice,
+                        sender,
+                        &format!(
+                            "You can use {} again in {} seconds.",
+                            stage.name,
+                            left.as_secs() + 1
+                        ),
+                    );
+                    return;
+                }
+            }
+            for stage in &stages {
+                self.rate_limiter
+                    .used(&config.limits, sender, stage.name, now);
+            }
+        }
+        let description = stages
+            .iter()
+            .map(|stage| stage.name)
+            .collect::<Vec<_>>()
+            .join(" | ");
+        let mut plugins: Vec<String> = stages
+            .iter()
+            .map(|stage| stage.plugin.health.name().to_owned())
+            .collect();
+        plugins.sort();
+        plugins.dedup();
+        let timeout = Duration::from_secs(self.config.lock().unwrap().workers.command_timeout_secs);
+        let sink = Arc::new(DeadlineSink {
+            outbox: Arc::clone(&self.irc_bridge.outbox),
+            timed_out: AtomicBool::new(false),
+        });
+        let on_timeout = {
+            let sink = Arc::clone(&sink);
+            let target = target.clone();
+            let msg = format!("{}: {} timed out.", sender, description);
+            move || {
+                sink.timed_out.store(true, Ordering::SeqCst);
+                sink.outbox
+                    .send_chunked(MessageKind::Privmsg, target.name(), &msg);
+            }
+        };
+        let job = {
+            let target = target.clone();
+            let sender = sender.to_owned();
+            let command_errors = Arc::clone(&self.command_errors);
+            move || match pipeline::run(stages, &*sink, target.as_target(), &sender) {
+                Ok(()) => {}
+                Err(StageError::Opts(e)) => {
+                    sink.send_chunked(MessageKind::Privmsg, target.name(), &format!("{:?}", e));
+                }
+                Err(StageError::Panic(name, msg)) => {
+                    record_error(
+                        &command_errors,
+                        format!("Command '{}' panicked: {}", name, msg),
+                    );
+                    sink.send_chunked(
+                        MessageKind::Privmsg,
+                        target.name(),
+                        &format!("{} crashed: {}", name, msg),
+                    );
+                }
+                Err(StageError::Command(name, e)) => {
+                    let detail = describe_command_error(&name, &e);
+                    eprintln!("{}", detail);
+                    record_error(&command_errors, detail);
+                    if report_errors {
+                        sink.send_chunked(
+                            MessageKind::Privmsg,
+                            target.name(),
+                            &format!("Error in {}: {}", name, e),
+                        );
                     }
-                } else {
-                    let distance = damerau_levenshtein(command, cmd.name);
-                    if distance < closest_match.1 {
-                        closest_match = (cmd.name, distance);
+                }
+            }
+        };
+        let queued = self.workers.execute_with_deadline(
+            format!("command '{}' from {}", description, sender),
+            plugins,
+            timeout,
+            job,
+            on_timeout,
+        );
+        if !queued {
+            self.irc_bridge.msg(
+                target.name(),
+                &format!("Sorry {}, I'm too busy right now. Try again later.", sender),
+            );
+        }
+    }
+    /// Find the command that `command` invokes.
+    ///
+    /// If there is no such command, or the sender isn't allowed to use it, they are told so.
+    fn resolve_stage(
+        &self,
+        command: &str,
+        target: &ReplyTarget,
+        sender: &str,
+        role: Role,
+        disabled: &[String],
+    ) -> Result<Stage, Unresolved> {
+        let (name, args) = match optparse::split_command(command) {
+            Some(split) => split,
+            None => {
+                self.irc_bridge
+                    .msg(target.name(), &format!("{}: Empty command.", sender));
+                return Err(Unresolved::Reported);
+            }
+        };
+        let args = args.to_owned();
+        let config = self.config.lock().unwrap();
+        let mut candidates: Vec<&str> = config.aliases.keys().map(|alias| &alias[..]).collect();
+        for (plugin_name, plugin) in &self.plugins {
+            let crashed =
+                plugin.health.is_disabled() && config.plugin_enabled(target.name(), plugin_name);
+            if crashed && plugin.meta.commands.iter().any(|cmd| cmd.name == name) {
+                self.irc_bridge.msg(
+                    target.name(),
+                    &format!(
+                        "Sorry {}, {} kept crashing, so it's disabled until its plugin is reloaded.",
+                        sender, name
+                    ),
+                );
+                return Err(Unresolved::Reported);
+            }
+            if disabled.contains(plugin_name) {
+                continue;
+            }
+            for cmd in &plugin.meta.commands {
+                if name == cmd.name {
+                    if role < cmd.role {
+                        self.irc_bridge.msg(
+                            target.name(),
+                            &format!(
+                                "Sorry {}, {} can only be used by those with the {} role.",
+                                sender, cmd.name, cmd.role
+                            ),
+                        );
+                        return Err(Unresolved::Reported);
                     }
+                    return Ok(Stage {
+                        name: cmd.name,
+                        plugin: plugin.handle(),
+                        fun: cmd.fun,
+                        opts: cmd.opts.clone(),
+                        args,
+                    });
                 }
+                candidates.push(cmd.name);
             }
         }
-        if !match_found {
-            let _ = irc.privmsg(
-                channel.name(),
+        let suggested = suggest::suggest(&name, candidates, &config.suggestions);
+        Err(Unresolved::Unknown(name, suggested))
+    }
+    /// Handle the built-in admin command, which does the same things as the IPC commands.
+    fn handle_admin(&mut self, target: &ReplyTarget, sender: &str, role: Role, arg: &str) {
+        if role < Role::Admin {
+            self.irc_bridge.msg(
+                target.name(),
                 &format!(
-                    "Unknown command: {}. Did you mean '{}'?",
-                    command, closest_match.0
+                    "Sorry {}, {} can only be used by those with the {} role.",
+                    sender,
+                    ADMIN_COMMAND,
+                    Role::Admin
                 ),
             );
+            return;
+        }
+        let config = Arc::clone(&self.config);
+        let reply = admin::execute(arg.trim(), self, &mut config.lock().unwrap());
+        for line in reply.lines() {
+            self.irc_bridge
+                .msg(target.name(), &format!("{}: {}", sender, line));
         }
     }
-    fn delegate_non_command(
-        &mut self,
-        irc: &Arc<Irc>,
-        channel: &Arc<Channel>,
-        sender: &Arc<ChannelUser>,
-        message: &str,
-    ) {
-        for plugin in self.plugins.values_mut() {
-            let plugin = plugin.plugin.clone();
+    fn delegate_non_command(&mut self, target: &ReplyTarget, sender: &str, message: &str) {
+        let disabled = self.disabled_plugins(target);
+        for (name, plugin) in &self.plugins {
+            if disabled.contains(name) {
+                continue;
+            }
+            let plugin = plugin.handle();
             let message = message.to_owned();
-            let irc = Arc::clone(irc);
-            let channel = Arc::clone(channel);
-            let sender = Arc::clone(sender);
-            std::thread::spawn(move || {
-                plugin
-                    .lock()
-                    .unwrap()
-                    .channel_msg(&message, Context::new(&irc, &channel, &sender));
+            let outbox = Arc::clone(&self.irc_bridge.outbox);
+            let target = target.clone();
+            let sender = sender.to_owned();
+            let command_errors = Arc::clone(&self.command_errors);
+            let name = name.clone();
+            let description = format!("message to {} from {}", name, sender);
+            let plugins = vec![name.clone()];
+            self.workers.execute(description, plugins, move || {
+                let ctx = Context::new(
+                    &*outbox,
+                    target.as_target(),
+                    &sender,
+                    &plugin.storage,
+                    &*plugin.schedule,
+                );
+                let result = plugin.health.guard("message handler", || {
+                    let mut plugin = lock_plugin(&plugin.plugin);
+                    match target {
+                        ReplyTarget::Channel(_) => plugin.channel_msg(&message, ctx),
+                        ReplyTarget::User(_) => plugin.private_msg(&message, ctx),
+                    }
+                });
+                if let Err(msg) = result {
+                    record_error(
+                        &command_errors,
+                        format!("Plugin '{}' panicked on a message: {}", name, msg),
+                    );
+                }
             });
         }
     }
-    pub fn load_plugin(&mut self, name: &str) -> Result<(), Box<Error>> {
-        let pc = PluginContainer::load(name)?;
+    /// Names of the plugins that are disabled in `target`.
+    fn disabled_plugins(&self, target: &ReplyTarget) -> Vec<String> {
+        let config = self.config.lock().unwrap();
+        self.plugins
+            .iter()
+            .filter(|&(name, plugin)| {
+                plugin.health.is_disabled() || !config.plugin_enabled(target.name(), name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+    /// Where the calls of `plugin` that aren't about one channel send their messages.
+    fn enabled_sink(&self, plugin: &str) -> EnabledSink {
+        EnabledSink {
+            outbox: Arc::clone(&self.irc_bridge.outbox),
+            config: Arc::clone(&self.config),
+            plugin: plugin.to_owned(),
+        }
+    }
+    /// Notify every plugin about a membership or topic event.
+    fn handle_event(&mut self, event: Event) {
+        let disabled = match event.channel() {
+            Some(channel) => self.disabled_plugins(&ReplyTarget::Channel(channel.to_owned())),
+            None => Vec::new(),
+        };
+        for (name, plugin) in &self.plugins {
+            if plugin.health.is_disabled() || disabled.contains(name) {
+                continue;
+            }
+            let plugin = plugin.handle();
+            let sink = self.enabled_sink(name);
+            let event = event.clone();
+            let command_errors = Arc::clone(&self.command_errors);
+            let name = name.clone();
+            let description = format!("event for {}", name);
+            let plugins = vec![name.clone()];
+            self.workers.execute(description, plugins, move || {
+                let result = plugin.health.guard("event handler", || {
+                    event.dispatch(
+                        &mut *lock_plugin(&plugin.plugin),
+                        &sink,
+                        &plugin.storage,
+                        &*plugin.schedule,
+                    )
+                });
+                if let Err(msg) = result {
+                    record_error(
+                        &command_errors,
+                        format!("Plugin '{}' panicked on an event: {}", name, msg),
+                    );
+                }
+            });
+        }
+    }
+    /// Join the configured channels and the ones the bot was in before, once authentication
+    /// allows it.
+    fn join_channels_when_ready(&mut self) {
+        let config = self.config.lock().unwrap();
+        if self.auth.take_join(&config.auth, Instant::now()) {
+            let mut channels = config.bot.channels.clone();
+            for c in &self.irc_bridge.channels {
+                if !channels.iter().any(|other| other.eq_ignore_ascii_case(c)) {
+                    channels.push(c.clone());
+                }
+            }
+            for c in &channels {
+                self.irc_bridge.join(c);
+            }
+        }
+    }
+    /// The connection to the server was lost.
+    pub fn disconnected(&mut self) {
+        self.irc_bridge.handle = None;
+        self.irc_bridge.outbox.set_connected(false);
+        self.auth = Auth::default();
+        // Users can quit unseen while we are away
+        self.users = Users::default();
+        self.nick = Nick::new(&self.config.lock().unwrap().bot.nick);
+    }
+    /// The nick the bot currently has.
+    pub fn nick(&self) -> &str {
+        &self.nick.current
+    }
+    /// Try to get the configured nick back, if it's time for that.
+    fn reclaim_nick(&mut self) {
+        let lines = {
+            let config = self.config.lock().unwrap();
+            self.nick.reclaim(&config.bot, &config.auth, Instant::now())
+        };
+        if let Some(ref irc) = self.irc_bridge.handle {
+            for line in lines {
+                let _ = irc.raw(line);
+            }
+        }
+    }
+    /// Fire the timers and make the scheduled calls that are due.
+    pub fn fire_timers(&mut self) {
+        if !self.irc_bridge.is_ready() {
+            // Plugins can't do anything useful until we're connected
+            return;
+        }
+        self.join_channels_when_ready();
+        self.reclaim_nick();
+        let now = Instant::now();
+        // The plugin, what kind of call it is, and the call
+        let mut calls: Vec<(String, &str, NetworkCall)> = Vec::new();
+        let mut i = 0;
+        while i < self.timers.len() {
+            let scheduled = &mut self.timers[i];
+            if scheduled.due > now {
+                i += 1;
+                continue;
+            }
+            let container = &self.plugins[&scheduled.plugin];
+            let timer = &container.meta.timers[scheduled.index];
+            if !container.health.is_disabled() {
+                let fun = timer.fun;
+                calls.push((scheduled.plugin.clone(), "timer", Box::new(fun)));
+            }
+            match timer.interval {
+                Some(interval) => {
+                    scheduled.due = now + interval;
+                    i += 1;
+                }
+                None => {
+                    self.timers.remove(i);
+                }
+            }
+        }
+        for (name, container) in &self.plugins {
+            for call in container.schedule.take_due(now) {
+                if !container.health.is_disabled() {
+                    let fun: NetworkCall = Box::new(move |plugin: &mut Plugin, ctx| {
+                        (call.fun)(plugin, &call.data, ctx)
+                    });
+                    calls.push((name.clone(), "scheduled call", fun));
+                }
+            }
+        }
+        for (name, what, fun) in calls {
+            let plugin = self.plugins[&name].handle();
+            let sink = self.enabled_sink(&name);
+            let command_errors = Arc::clone(&self.command_errors);
+            let description = format!("{} of {}", what, name);
+            let plugins = vec![name.clone()];
+            let what = what.to_owned();
+            self.workers.execute(description, plugins, move || {
+                let result = plugin.health.guard(&what, || {
+                    fun(
+                        &mut *lock_plugin(&plugin.plugin),
+                        NetworkContext::new(&sink, &plugin.storage, &*plugin.schedule),
+                    )
+                });
+                if let Err(msg) = result {
+                    record_error(
+                        &command_errors,
+                        format!("Plugin '{}' panicked in a {}: {}", name, what, msg),
+                    );
+                }
+            });
+        }
+    }
+    /// Let the loaded plugins know about their changed settings.
+    ///
+    /// Returns the plugins that failed to apply their new settings, along with the errors.
+    pub fn reconfigure_plugins(&mut self, old: &Config, new: &Config) -> Vec<(String, String)> {
+        let mut failures = Vec::new();
+        for (name, container) in &self.plugins {
+            let settings = new.plugin_settings(name);
+            if settings == old.plugin_settings(name) {
+                continue;
+            }
+            let mut plugin =
+                match try_lock_plugin(&container.plugin, plugin_container::LOCK_TIMEOUT) {
+                    Some(plugin) => plugin,
+                    None => {
+                        failures.push((
+                            name.clone(),
+                            "busy, reload it to apply the new settings".to_owned(),
+                        ));
+                        continue;
+                    }
+                };
+            let result = container
+                .health
+                .guard("configure", || plugin.configure(&settings));
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => failures.push((name.clone(), e.to_string())),
+                Err(msg) => failures.push((name.clone(), format!("panicked: {}", msg))),
+            }
+        }
+        failures
+    }
+    /// Add a loaded plugin, and schedule its timers.
+    fn insert_plugin(&mut self, name: &str, pc: PluginContainer) {
+        let now = Instant::now();
+        for (index, timer) in pc.meta.timers.iter().enumerate() {
+            self.timers.push(ScheduledTimer {
+                plugin: name.to_owned(),
+                index,
+                due: now + timer.delay,
+            });
+        }
         self.plugins.insert(name.to_owned(), pc);
+    }
+    /// Remove a plugin, and cancel its timers and scheduled calls.
+    fn remove_plugin(&mut self, name: &str) -> Option<PluginContainer> {
+        self.timers.retain(|t| t.plugin != name);
+        let pc = self.plugins.remove(name)?;
+        // Jobs that are still running can't schedule anything anymore either
+        pc.schedule.close();
+        Some(pc)
+    }
+    /// The storage of the plugin `name`.
+    ///
+    /// Every instance of a plugin gets the same one, so that jobs still running for an old
+    /// instance don't overwrite what the new one stores.
+    fn plugin_storage(&mut self, name: &str) -> io::Result<Storage> {
+        // The name goes into paths, and admins can load plugins from IRC
+        if !valid_plugin_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "plugin names can only have letters, digits, '-' and '_'",
+            ));
+        }
+        if let Some(storage) = self.storages.get(name) {
+            return Ok(storage.clone());
+        }
+        let storage = Storage::open(&self.storage_dir, name)?;
+        self.storages.insert(name.to_owned(), storage.clone());
+        Ok(storage)
+    }
+    pub fn load_plugin(&mut self, name: &str, config: &Config) -> Result<(), Box<Error>> {
+        let storage = self.plugin_storage(name)?;
+        let pc = PluginContainer::load(name, config, storage)?;
+        self.insert_plugin(name, pc);
         Ok(())
     }
     pub fn unload_plugin(&mut self, name: &str) -> bool {
-        self.plugins.remove(name).is_some()
+        self.remove_plugin(name).is_some()
     }
-    pub fn reload_plugin(&mut self, name: &str) -> Result<(), Box<Error>> {
-        self.plugins.remove(name);
-        let plugin = PluginContainer::load(name)?;
-        self.plugins.insert(name.into(), plugin);
+    pub fn reload_plugin(&mut self, name: &str, config: &Config) -> Result<(), Box<Error>> {
+        // The old instance must be gone before loading, otherwise the library wouldn't be
+        // reopened, so save its state first.
+        // The same goes for the jobs that are queued or still running for it.
+        if self
+            .plugins
+            .get(name)
+            .map_or(false, PluginContainer::in_use)
+        {
+            return Err("it's still busy, try again once its calls have finished".into());
+        }
+        // A plugin that kept crashing likely has broken state, so it starts fresh.
+        let state = match self.remove_plugin(name) {
+            Some(ref old) if !old.health.is_disabled() => {
+                match try_lock_plugin(&old.plugin, plugin_container::LOCK_TIMEOUT) {
+                    Some(mut plugin) => old
+                        .health
+                        .guard("save_state", || plugin.save_state())
+                        .unwrap_or(None),
+                    None => {
+                        eprintln!("Plugin '{}' is busy, reloading it without its state.", name);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let storage = self.plugin_storage(name)?;
+        let mut plugin = PluginContainer::load(name, config, storage.clone())?;
+        if let Some(state) = state {
+            let restored = plugin
+                .health
+                .guard("restore_state", || {
+                    lock_plugin(&plugin.plugin).restore_state(&state)
+                })
+                .unwrap_or_else(|msg| Err(msg.into()));
+            if let Err(e) = restored {
+                eprintln!(
+                    "Plugin '{}' failed to restore its state, starting fresh: {}",
+                    name, e
+                );
+                drop(plugin);
+                plugin = PluginContainer::load(name, config, storage)?;
+            }
+        }
+        self.insert_plugin(name, plugin);
         Ok(())
     }
 }
 
+/// Whether `name` can be used as the name of a plugin, which is also part of the paths of its
+/// library and its storage.
+fn valid_plugin_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A call into a plugin that gets a network context, like a timer.
+type NetworkCall = Box<FnOnce(&mut Plugin, NetworkContext) + Send>;
+
+/// A plugin timer that is waiting to fire.
+struct ScheduledTimer {
+    /// Name of the plugin the timer belongs to.
+    plugin: String,
+    /// Index into the timers of the plugin's metadata.
+    index: usize,
+    /// When the timer should fire next.
+    due: Instant,
+}
+
+/// How many command failures are remembered for IPC clients.
+const MAX_COMMAND_ERRORS: usize = 20;
+
+/// Remember an error for IPC clients.
+fn record_error(command_errors: &Mutex<VecDeque<String>>, detail: String) {
+    let mut errors = command_errors.lock().unwrap();
+    if errors.len() == MAX_COMMAND_ERRORS {
+        errors.pop_front();
+    }
+    errors.push_back(detail);
+}
+
+/// Describe a failed command, including the whole chain of causes.
+fn describe_command_error(name: &str, e: &CommandError) -> String {
+    let mut detail = format!("Command '{}' failed: {}", name, e.message());
+    for cause in e.causes() {
+        detail.push_str("\n  caused by: ");
+        detail.push_str(cause);
+    }
+    detail
+}
+
+/// Passes messages on to the outbox until the command times out.
+struct DeadlineSink {
+    outbox: Arc<Outbox>,
+    timed_out: AtomicBool,
+}
+
+impl Sink for DeadlineSink {
+    fn send(&self, kind: MessageKind, target: &str, text: &str) {
+        if !self.timed_out.load(Ordering::SeqCst) {
+            self.outbox.send(kind, target, text);
+        }
+    }
+}
+
+/// Passes messages of a plugin on to the outbox, except to the channels where it's disabled.
+struct EnabledSink {
+    outbox: Arc<Outbox>,
+    config: Arc<Mutex<Config>>,
+    plugin: String,
+}
+
+impl Sink for EnabledSink {
+    fn send(&self, kind: MessageKind, target: &str, text: &str) {
+        if self
+            .config
+            .lock()
+            .unwrap()
+            .plugin_enabled(target, &self.plugin)
+        {
+            self.outbox.send(kind, target, text);
+        }
+    }
+}
+
+/// Owned version of `plugin_api::Target`, so it can be moved into plugin threads.
+#[derive(Clone)]
+enum ReplyTarget {
+    Channel(String),
+    User(String),
+}
+
+impl ReplyTarget {
+    fn name(&self) -> &str {
+        self.as_target().name()
+    }
+    fn as_target(&self) -> Target {
+        match *self {
+            ReplyTarget::Channel(ref name) => Target::Channel(name),
+            ReplyTarget::User(ref nick) => Target::User(nick),
+        }
+    }
+}
+
+/// Why a command couldn't be resolved.
+enum Unresolved {
+    /// The user has already been told why.
+    Reported,
+    /// There is no such command. Contains the name and the suggested commands.
+    Unknown(String, Vec<String>),
+}
+
+/// What users say to run the command that was offered to them instead of an unknown one.
+const ACCEPT_OFFER: &str = "yes";
+/// How long an offered command can be accepted for.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Directory for the data of the core, within the storage directory. Keeping it apart from the
+/// stores of the plugins means that no plugin name can clash with it.
+const CORE_STORAGE_DIR: &str = "core";
+/// Storage namespace of the ignore list.
+const IGNORE_LIST_NAMESPACE: &str = "ignore";
+/// Storage namespace of the plugins enabled and disabled by admins.
+const PLUGIN_OVERRIDES_NAMESPACE: &str = "plugin-overrides";
+
+/// Name of the built-in command that gives access to the admin commands.
+const ADMIN_COMMAND: &str = "admin";
+
+/// If the command at the start of `command` is an alias, replace it with what it stands for.
+///
+/// Arguments baked into the alias go before the ones given by the user.
+fn expand_alias(aliases: &HashMap<String, String>, command: &str) -> String {
+    let command = command.trim_start();
+    let (name, rest) = match command.find(char::is_whitespace) {
+        Some(pos) => command.split_at(pos),
+        None => (command, ""),
+    };
+    match aliases.get(&name.to_lowercase()) {
+        Some(expansion) => format!("{}{}", expansion, rest),
+        None => command.to_owned(),
+    }
+}
+
 fn is_valid_command(message: &str, prefix: &str) -> bool {
     // A valid command is `prefix` immediately succeeded by an alphabetic character
     let ml = message.len();
@@ -274,29 +1188,93 @@ impl SharedCore {
 }
 
 impl Listener for SharedCore {
+    fn any(&mut self, irc: Arc<Irc>, event: &hiirc::Event) {
+        if let hiirc::Event::Message(ref msg) = *event {
+            let mut core = self.lock();
+            let core = &mut *core;
+            let replies = {
+                let config = core.config.lock().unwrap();
+                core.auth.handle(msg, &config.auth, &config.bot.nick)
+            };
+            for line in replies {
+                let _ = irc.raw(line);
+            }
+            core.users.observe(msg, &core.nick.current);
+            if let Some(line) = Users::who_query(msg, &core.nick.current) {
+                let _ = irc.raw(line);
+            }
+            core.nick.observe(msg);
+            if let Some(event) = Event::from_message(msg) {
+                core.irc_bridge.observe(&event, &core.nick.current);
+                core.nick
+                    .observe_event(&event, &core.config.lock().unwrap().bot);
+                core.handle_event(event);
+            }
+        }
+    }
     fn welcome(&mut self, irc: Arc<Irc>) {
-        let mut core = self.0.lock().unwrap();
-        for c in &core.config.lock().unwrap().bot.channels {
-            irc.join(c, None).unwrap();
+        let mut core = self.lock();
+        let core = &mut *core;
+        let lines = {
+            let config = core.config.lock().unwrap();
+            core.auth
+                .welcome(&config.auth, &config.bot.nick, Instant::now())
+        };
+        for line in lines {
+            let _ = irc.raw(line);
         }
         core.irc_bridge.init(irc);
+        core.join_channels_when_ready();
     }
     fn channel_msg(
         &mut self,
-        irc: Arc<Irc>,
+        _irc: Arc<Irc>,
         channel: Arc<Channel>,
         sender: Arc<ChannelUser>,
         message: &str,
     ) {
-        self.lock().channel_msg(&irc, &channel, &sender, message);
+        self.lock()
+            .channel_msg(channel.name(), &sender.nickname(), message);
     }
-    fn error_msg(&mut self, _irc: Arc<Irc>, code: &hiirc::Code, msg: &hiirc::Message) {
+    fn private_msg(&mut self, _irc: Arc<Irc>, sender: &str, message: &str) {
+        self.lock().private_msg(sender, message);
+    }
+    fn error_msg(&mut self, irc: Arc<Irc>, code: &hiirc::Code, msg: &hiirc::Message) {
         match code {
             hiirc::Code::ErrNicknameinuse => {
-                eprintln!("Nickname already in use. Pick another one.");
-                self.0.lock().unwrap().quit = true;
+                let mut core = self.lock();
+                let core = &mut *core;
+                if core.irc_bridge.handle.is_some() {
+                    // Trying to reclaim the configured nick didn't work out this time
+                    return;
+                }
+                let config = core.config.lock().unwrap();
+                let nick = core.nick.next(&config.bot);
+                eprintln!("Nickname already in use. Trying {} instead.", nick);
+                let _ = irc.nick(nick);
             }
             _ => eprintln!("Error. code: {:?}, msg: {:?}", code, msg),
         }
     }
 }
+
+#[test]
+fn test_expand_alias() {
+    let mut aliases = HashMap::new();
+    aliases.insert("g".to_owned(), "search".to_owned());
+    aliases.insert("udl".to_owned(), "ud --loose".to_owned());
+    assert_eq!(expand_alias(&aliases, "g rust lang"), "search rust lang");
+    assert_eq!(expand_alias(&aliases, "UDL yeet"), "ud --loose yeet");
+    assert_eq!(expand_alias(&aliases, "udl"), "ud --loose");
+    assert_eq!(expand_alias(&aliases, "gg rust"), "gg rust");
+}
+
+#[test]
+fn test_valid_plugin_name() {
+    assert!(valid_plugin_name("linktitle"));
+    assert!(valid_plugin_name("my-plugin_2"));
+    assert!(!valid_plugin_name(""));
+    assert!(!valid_plugin_name("../../x"));
+    assert!(!valid_plugin_name("/etc/x"));
+    assert!(!valid_plugin_name("x\\y"));
+}
@@ -0,0 +1,144 @@
+//! Membership and topic events that get forwarded to plugins.
+
+use hiirc::{Code, Message, Prefix};
+use plugin_api::storage::Storage;
+use plugin_api::{Context, NetworkContext, Plugin, Scheduler, Sink, Target};
+
+/// An IRC event that plugins are notified about.
+#[derive(Clone, Debug)]
+pub(crate) enum Event {
+    Join {
+        channel: String,
+        nick: String,
+    },
+    Part {
+        channel: String,
+        nick: String,
+        reason: Option<String>,
+    },
+    Kick {
+        channel: String,
+        kicker: String,
+        kicked: String,
+        reason: Option<String>,
+    },
+    Quit {
+        nick: String,
+        reason: Option<String>,
+    },
+    NickChange {
+        old: String,
+        new: String,
+    },
+    Topic {
+        channel: String,
+        setter: String,
+        topic: String,
+    },
+}
+
+impl Event {
+    /// Try to interpret a raw IRC message as an event.
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let nick = match msg.prefix {
+            Some(Prefix::User(ref user)) => user.nickname.clone(),
+            _ => return None,
+        };
+        let params = params(msg);
+        let param = |i: usize| params.get(i).map(|p| p.to_string());
+        let event = match msg.code {
+            Code::Join => Event::Join {
+                channel: param(0)?,
+                nick,
+            },
+            Code::Part => Event::Part {
+                channel: param(0)?,
+                nick,
+                reason: param(1),
+            },
+            Code::Kick => Event::Kick {
+                channel: param(0)?,
+                kicker: nick,
+                kicked: param(1)?,
+                reason: param(2),
+            },
+            Code::Quit => Event::Quit {
+                nick,
+                reason: param(0),
+            },
+            Code::Nick => Event::NickChange {
+                old: nick,
+                new: param(0)?,
+            },
+            Code::Topic => Event::Topic {
+                channel: param(0)?,
+                setter: nick,
+                topic: param(1).unwrap_or_default(),
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+    /// The channel the event happened in, if it's about a single channel.
+    pub fn channel(&self) -> Option<&str> {
+        match *self {
+            Event::Join { ref channel, .. }
+            | Event::Part { ref channel, .. }
+            | Event::Kick { ref channel, .. }
+            | Event::Topic { ref channel, .. } => Some(channel),
+            Event::Quit { .. } | Event::NickChange { .. } => None,
+        }
+    }
+    /// Notify `plugin` about this event.
+    pub fn dispatch(
+        &self,
+        plugin: &mut Plugin,
+        sink: &Sink,
+        storage: &Storage,
+        scheduler: &Scheduler,
+    ) {
+        let ctx =
+            |channel, nick| Context::new(sink, Target::Channel(channel), nick, storage, scheduler);
+        let network_ctx = NetworkContext::new(sink, storage, scheduler);
+        match *self {
+            Event::Join {
+                ref channel,
+                ref nick,
+            } => plugin.user_join(ctx(channel, nick)),
+            Event::Part {
+                ref channel,
+                ref nick,
+                ref reason,
+            } => plugin.user_part(reason.as_ref().map(|s| &s[..]), ctx(channel, nick)),
+            Event::Kick {
+                ref channel,
+                ref kicker,
+                ref kicked,
+                ref reason,
+            } => plugin.user_kick(
+                kicked,
+                reason.as_ref().map(|s| &s[..]),
+                ctx(channel, kicker),
+            ),
+            Event::Quit {
+                ref nick,
+                ref reason,
+            } => plugin.user_quit(nick, reason.as_ref().map(|s| &s[..]), network_ctx),
+            Event::NickChange { ref old, ref new } => plugin.nick_change(old, new, network_ctx),
+            Event::Topic {
+                ref channel,
+                ref setter,
+                ref topic,
+            } => plugin.topic_change(topic, ctx(channel, setter)),
+        }
+    }
+}
+
+/// The middle parameters of a message, followed by the trailing one (if any).
+fn params(msg: &Message) -> Vec<&str> {
+    let mut params: Vec<&str> = msg.args.iter().map(|s| &s[..]).collect();
+    if let Some(ref suffix) = msg.suffix {
+        params.push(suffix);
+    }
+    params
+}
@@ -0,0 +1,126 @@
+//! Rate limiting of commands.
+
+use crate::config::{Limits, Rate};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Keeps track of who used which commands when.
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    /// When the commands were invoked, per lowercase nick.
+    users: HashMap<String, VecDeque<Instant>>,
+    /// When the commands were invoked, per lowercase channel name.
+    channels: HashMap<String, VecDeque<Instant>>,
+    /// When commands with a cooldown were last used, per lowercase nick and command.
+    last_used: HashMap<(String, String), Instant>,
+}
+
+impl RateLimiter {
+    /// Record a command invocation by `nick` in `channel`, unless it would go over a limit.
+    ///
+    /// Returns whether the command is allowed.
+    pub fn allow(
+        &mut self,
+        limits: &Limits,
+        nick: &str,
+        channel: Option<&str>,
+        now: Instant,
+    ) -> bool {
+        let nick = nick.to_lowercase();
+        let channel = channel.map(str::to_lowercase);
+        if let Some(ref rate) = limits.user {
+            if over_limit(&mut self.users, &nick, rate, now) {
+                return false;
+            }
+        }
+        if let (Some(rate), Some(channel)) = (limits.channel.as_ref(), channel.as_ref()) {
+            if over_limit(&mut self.channels, channel, rate, now) {
+                return false;
+            }
+        }
+        if limits.user.is_some() {
+            self.users.entry(nick).or_default().push_back(now);
+        }
+        if let (true, Some(channel)) = (limits.channel.is_some(), channel) {
+            self.channels.entry(channel).or_default().push_back(now);
+        }
+        true
+    }
+    /// How long `nick` still has to wait before using `command` again, if at all.
+    pub fn cooldown(
+        &self,
+        limits: &Limits,
+        nick: &str,
+        command: &str,
+        now: Instant,
+    ) -> Option<Duration> {
+        let cooldown = Duration::from_secs(*limits.cooldowns.get(command)?);
+        let last = self
+            .last_used
+            .get(&(nick.to_lowercase(), command.to_owned()))?;
+        cooldown
+            .checked_sub(now.duration_since(*last))
+            .filter(|left| *left > Duration::from_secs(0))
+    }
+    /// Record that `nick` used `command`, so its cooldown starts.
+    pub fn used(&mut self, limits: &Limits, nick: &str, command: &str, now: Instant) {
+        // Forget about the cooldowns that are over
+        self.last_used.retain(
+            |&(_, ref command), last| match limits.cooldowns.get(command) {
+                Some(&secs) => now.duration_since(*last) < Duration::from_secs(secs),
+                None => false,
+            },
+        );
+        if limits.cooldowns.contains_key(command) {
+            self.last_used
+                .insert((nick.to_lowercase(), command.to_owned()), now);
+        }
+    }
+}
+
+/// Whether another invocation would exceed `rate` for `key`.
+fn over_limit(
+    hits: &mut HashMap<String, VecDeque<Instant>>,
+    key: &str,
+    rate: &Rate,
+    now: Instant,
+) -> bool {
+    let window = Duration::from_secs(rate.seconds);
+    // Forget about the invocations that are out of the window
+    hits.retain(|_, times| {
+        while times
+            .front()
+            .map_or(false, |&t| now.duration_since(t) >= window)
+        {
+            times.pop_front();
+        }
+        !times.is_empty()
+    });
+    hits.get(key).map_or(0, |times| times.len()) >= rate.commands
+}
+
+#[test]
+fn test_rate_limiter() {
+    let mut limits = Limits::default();
+    limits.user = Some(Rate {
+        commands: 2,
+        seconds: 10,
+    });
+    limits.cooldowns.insert("search".into(), 5);
+    let mut limiter = RateLimiter::default();
+    let start = Instant::now();
+    assert!(limiter.allow(&limits, "Spammer", Some("#a"), start));
+    assert!(limiter.allow(&limits, "spammer", Some("#b"), start));
+    assert!(!limiter.allow(&limits, "spammer", Some("#a"), start));
+    assert!(limiter.allow(&limits, "calm", Some("#a"), start));
+    assert!(limiter.allow(&limits, "spammer", None, start + Duration::from_secs(10)));
+    limiter.used(&limits, "calm", "search", start);
+    let later = start + Duration::from_secs(2);
+    assert_eq!(
+        limiter.cooldown(&limits, "Calm", "search", later),
+        Some(Duration::from_secs(3))
+    );
+    assert_eq!(limiter.cooldown(&limits, "spammer", "search", later), None);
+    let after = start + Duration::from_secs(5);
+    assert_eq!(limiter.cooldown(&limits, "calm", "search", after), None);
+}
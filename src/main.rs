@@ -3,10 +3,14 @@
 //! Boncarobot is an IRC bot whose functionality is implemented through plugins.
 //!
 //! It can also be controlled locally through IPC.
+//!
+//! `boncarobot --console [nick]` runs the bot without IRC, reading messages from stdin.
 
+extern crate base64;
 extern crate distance;
 extern crate hiirc;
 extern crate libloading;
+extern crate native_tls;
 extern crate plugin_api;
 extern crate scaproust;
 #[macro_use]
@@ -14,16 +18,43 @@ extern crate serde_derive;
 extern crate split_whitespace_rest;
 extern crate toml;
 
+mod admin;
+mod auth;
 mod config;
+mod connection;
+mod console;
 mod core;
+mod event;
 mod ipc_control;
+mod limits;
+mod nick;
+mod outbox;
+mod pipeline;
 mod plugin_container;
+mod proxy;
+mod suggest;
+mod users;
+mod workers;
 
 use crate::core::SharedCore;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 fn main() {
+    let mut args = std::env::args().skip(1);
+    let console_nick = match args.next() {
+        Some(ref arg) if arg == "--console" => {
+            Some(args.next().unwrap_or_else(|| "console".to_owned()))
+        }
+        Some(arg) => {
+            eprintln!("Unknown argument: {}", arg);
+            eprintln!("Usage: boncarobot [--console [nick]]");
+            return;
+        }
+        None => None,
+    };
+
     // If the configuration file does not exist, try copying over the template.
     if !std::path::Path::new(config::PATH).exists() {
         const TEMPLATE_PATH: &str = "boncarobot.template.toml";
@@ -43,18 +74,41 @@ fn main() {
     }
 
     let config = config::load().unwrap_or_else(|e| panic!("Error loading config: {}", e));
-    let mut server = config.server.url.clone();
     let nick = config.bot.nick.clone();
-    server.push_str(":6667");
     let config = Arc::new(Mutex::new(config));
 
     let core = SharedCore::new(Arc::clone(&config));
-    let core_clone = core.clone();
-    thread::spawn(move || {
-        let settings = hiirc::Settings::new(&server, &nick);
-        settings
-            .dispatch(core_clone)
-            .unwrap_or_else(|e| panic!("Failed to dispatch: {:?}", e));
+    thread::spawn({
+        let core = core.clone();
+        move || loop {
+            core.lock().fire_timers();
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+    if let Some(console_nick) = console_nick {
+        console::run(&core, &console_nick, &nick);
+        return;
+    }
+    let (proxy, password) = {
+        let config = config.lock().unwrap();
+        let proxy = proxy::Proxy::new(&config.server, auth::CAP_LS)
+            .unwrap_or_else(|e| panic!("Failed to set up the connection: {}", e));
+        (proxy, config.auth.server_password.clone())
+    };
+    thread::spawn({
+        let core = core.clone();
+        move || connection::run(&core, &proxy, &nick, password.as_ref().map(String::as_str))
+    });
+    thread::spawn({
+        let core = core.clone();
+        let outbox = Arc::clone(&core.lock().irc_bridge.outbox);
+        move || loop {
+            let msg = outbox.next();
+            if !core.lock().irc_bridge.write(&msg) {
+                // Disconnected in the meantime, so it goes out after reconnecting
+                outbox.put_back(msg);
+            }
+        }
     });
     ipc_control::listen(&core.0, &*config);
 }
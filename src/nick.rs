@@ -0,0 +1,126 @@
+//! Keeping track of the nick of the bot, and getting the configured one back when it's taken.
+
+use crate::config::{self, Reclaim};
+use crate::connection;
+use crate::event::Event;
+use hiirc::{Code, Message};
+use std::time::{Duration, Instant};
+
+/// The nick of the bot on the current connection.
+pub(crate) struct Nick {
+    /// The nick the bot has, or is registering with.
+    pub current: String,
+    /// How many alternate nicks have been tried.
+    alternates_tried: usize,
+    /// When the configured nick was last tried to be reclaimed.
+    last_reclaim: Option<Instant>,
+}
+
+impl Nick {
+    pub fn new(nick: &str) -> Self {
+        Self {
+            current: nick.to_owned(),
+            alternates_tried: 0,
+            last_reclaim: None,
+        }
+    }
+    /// Pick the next nick to register with, because the current one is taken.
+    pub fn next(&mut self, bot: &config::Bot) -> &str {
+        self.current = match bot.alternate_nicks.get(self.alternates_tried) {
+            Some(nick) => nick.clone(),
+            None => format!("{}{:03}", bot.nick, (connection::jitter() * 1000.0) as u32),
+        };
+        self.alternates_tried += 1;
+        // It was just found to be taken
+        self.last_reclaim = Some(Instant::now());
+        &self.current
+    }
+    /// Follow the changes of the nick.
+    pub fn observe(&mut self, msg: &Message) {
+        // The server tells which nick we registered with
+        if let (&Code::RplWelcome, Some(nick)) = (&msg.code, msg.args.get(0)) {
+            self.current = nick.clone();
+        }
+    }
+    /// Follow the changes of the nick, and notice when the configured one becomes free.
+    pub fn observe_event(&mut self, event: &Event, bot: &config::Bot) {
+        match *event {
+            Event::NickChange { ref old, ref new } if old.eq_ignore_ascii_case(&self.current) => {
+                self.current = new.clone();
+            }
+            Event::NickChange { ref old, .. } | Event::Quit { nick: ref old, .. }
+                if old.eq_ignore_ascii_case(&bot.nick) =>
+            {
+                // Try to get it right away
+                self.last_reclaim = None;
+            }
+            _ => {}
+        }
+    }
+    /// The lines to send for getting the configured nick back, if it's time for that.
+    pub fn reclaim(&mut self, bot: &config::Bot, auth: &config::Auth, now: Instant) -> Vec<String> {
+        if self.current.eq_ignore_ascii_case(&bot.nick) || bot.reclaim_nick_interval == 0 {
+            return Vec::new();
+        }
+        let interval = Duration::from_secs(bot.reclaim_nick_interval);
+        if self
+            .last_reclaim
+            .map_or(false, |last| now.duration_since(last) < interval)
+        {
+            return Vec::new();
+        }
+        self.last_reclaim = Some(now);
+        let services = |command: &str| {
+            format!(
+                "PRIVMSG NickServ :{} {} {}",
+                command, bot.nick, auth.account_password
+            )
+            .trim_end()
+            .to_owned()
+        };
+        match bot.reclaim_nick_with {
+            // Changes the nick too
+            Some(Reclaim::Regain) => vec![services("REGAIN")],
+            Some(Reclaim::Ghost) => vec![services("GHOST"), format!("NICK {}", bot.nick)],
+            None => vec![format!("NICK {}", bot.nick)],
+        }
+    }
+}
+
+#[test]
+fn test_nick() {
+    let mut bot: config::Bot = toml::from_str(
+        r##"
+        nick = "bonca"
+        channels = []
+        command-prefix = "."
+        alternate-nicks = ["bonca_"]
+        reclaim-nick-with = "ghost"
+        "##,
+    )
+    .unwrap();
+    let mut auth = config::Auth::default();
+    auth.account_password = "hunter2".into();
+    let mut nick = Nick::new(&bot.nick);
+    assert!(nick.reclaim(&bot, &auth, Instant::now()).is_empty());
+    assert_eq!(nick.next(&bot), "bonca_");
+    assert!(nick.next(&bot).starts_with("bonca"));
+    let now = Instant::now();
+    assert!(nick.reclaim(&bot, &auth, now).is_empty());
+    let later = now + Duration::from_secs(bot.reclaim_nick_interval);
+    assert_eq!(
+        nick.reclaim(&bot, &auth, later),
+        ["PRIVMSG NickServ :GHOST bonca hunter2", "NICK bonca"]
+    );
+    assert!(nick.reclaim(&bot, &auth, later).is_empty());
+    // Someone with the nick quit, so it's tried again right away
+    nick.observe_event(
+        &Event::Quit {
+            nick: "Bonca".into(),
+            reason: None,
+        },
+        &bot,
+    );
+    bot.reclaim_nick_with = None;
+    assert_eq!(nick.reclaim(&bot, &auth, later), ["NICK bonca"]);
+}
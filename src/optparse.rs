@@ -1,3 +1,5 @@
+use split_whitespace_rest::SplitWhitespace;
+
 #[test]
 fn test_parse() {
     let opt_defs = [
@@ -56,6 +58,33 @@ fn test_parse() {
     assert_eq!(args.unwrap(), expected);
 }
 
+#[test]
+fn test_parse_command() {
+    let opt_defs = [
+        OptDef {
+            short: 'f',
+            long: "flag",
+            help: "A simple flag",
+            takes_args: false,
+        },
+        OptDef {
+            short: 'm',
+            long: "multi",
+            help: "Takes multiple args",
+            takes_args: true,
+        },
+    ];
+    // The output of the previous command of a pipeline isn't parsed
+    let args = parse_command("", Some("a - b -m [x"), &opt_defs).unwrap();
+    assert_eq!(args.free, ["a - b -m [x"]);
+    let args = parse_command("-f free", Some(""), &opt_defs).unwrap();
+    assert_eq!(args.free, ["free"]);
+    assert_eq!(
+        parse_command(" ", None, &opt_defs).unwrap(),
+        ParsedOpts::default()
+    );
+}
+
 #[derive(Debug)]
 pub struct ParseError {
     kind: ParseErrorKind,
@@ -93,6 +122,34 @@ pub fn parse(mut cmdline: &str, opt_defs: &[OptDef]) -> Result<ParsedOpts, Parse
     }
 }
 
+/// Split a command into its name, lowercased, and its arguments.
+pub fn split_command(command: &str) -> Option<(String, &str)> {
+    let mut sw = SplitWhitespace::new(command);
+    let name = sw.next()?.to_lowercase();
+    Some((name, sw.rest_as_slice().trim()))
+}
+
+/// Parse the arguments of a command.
+///
+/// `input` is the output of the previous command of a pipeline. It's added as one more free
+/// argument as it is, rather than getting parsed.
+pub fn parse_command(
+    args: &str,
+    input: Option<&str>,
+    opt_defs: &[OptDef],
+) -> Result<ParsedOpts, ParseError> {
+    let args = args.trim();
+    let mut parsed_opts = if args.is_empty() {
+        ParsedOpts::default()
+    } else {
+        parse(args, opt_defs)?
+    };
+    if let Some(input) = input.filter(|input| !input.is_empty()) {
+        parsed_opts.free.push(input.to_owned());
+    }
+    Ok(parsed_opts)
+}
+
 fn parse_opt<'a>(
     cmdline: &'a str,
     opt_defs: &[OptDef],
@@ -256,6 +313,7 @@ fn lookup_by_long<'a>(opt_defs: &'a [OptDef], long: &str) -> Option<&'a OptDef>
     None
 }
 
+#[derive(Clone)]
 pub struct OptDef {
     pub short: char,
     pub long: &'static str,
@@ -0,0 +1,212 @@
+//! Flood-controlled queue of outgoing messages.
+//!
+//! Everything the bot says goes through here. Messages are rate limited with a token bucket,
+//! and targets take turns, so one chatty channel can't starve the others.
+
+use crate::config::Flood;
+use plugin_api::{MessageKind, Sink};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A message waiting to be sent.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Outgoing {
+    pub kind: MessageKind,
+    pub target: String,
+    pub text: String,
+}
+
+pub(crate) struct Outbox {
+    state: Mutex<State>,
+    /// Signaled when a message gets queued, or the connection comes up.
+    queued: Condvar,
+    burst: f64,
+    interval: Duration,
+    max_queue: usize,
+}
+
+struct State {
+    /// Queued messages, per target.
+    queues: HashMap<String, VecDeque<Outgoing>>,
+    /// Targets that have queued messages, in the order they get their turn.
+    turns: VecDeque<String>,
+    /// How many messages can be sent right now.
+    tokens: f64,
+    last_refill: Instant,
+    /// Whether there's a connection to send the messages to.
+    connected: bool,
+}
+
+impl Outbox {
+    pub fn new(flood: &Flood) -> Self {
+        Self {
+            state: Mutex::new(State {
+                queues: HashMap::new(),
+                turns: VecDeque::new(),
+                tokens: flood.burst as f64,
+                last_refill: Instant::now(),
+                connected: false,
+            }),
+            queued: Condvar::new(),
+            burst: flood.burst as f64,
+            interval: Duration::from_millis(flood.interval_ms),
+            max_queue: flood.max_queue,
+        }
+    }
+    /// Take the next message to send.
+    ///
+    /// Blocks until there is a message, the rate limit allows sending it, and there's a
+    /// connection to send it to.
+    pub fn next(&self) -> Outgoing {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if !state.connected {
+                state = self.queued.wait(state).unwrap();
+                continue;
+            }
+            self.refill(&mut state);
+            if state.tokens >= 1.0 {
+                if let Some(msg) = state.pop() {
+                    state.tokens -= 1.0;
+                    return msg;
+                }
+                state = self.queued.wait(state).unwrap();
+            } else {
+                let until_token = self.interval.mul_f64(1.0 - state.tokens);
+                state = self.queued.wait_timeout(state, until_token).unwrap().0;
+            }
+        }
+    }
+    /// Let the messages through, or hold them until there's a connection again.
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().connected = connected;
+        self.queued.notify_all();
+    }
+    /// Put back a message that couldn't be sent after all. It's the next one to be sent.
+    pub fn put_back(&self, msg: Outgoing) {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            ref mut queues,
+            ref mut turns,
+            ..
+        } = *state;
+        turns.retain(|target| *target != msg.target);
+        turns.push_front(msg.target.clone());
+        queues
+            .entry(msg.target.clone())
+            .or_insert_with(VecDeque::new)
+            .push_front(msg);
+        self.queued.notify_one();
+    }
+    /// How many messages are waiting, in total.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.queues.values().map(|q| q.len()).sum()
+    }
+    fn refill(&self, state: &mut State) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill);
+        let gained = elapsed.as_secs_f64() / self.interval.as_secs_f64();
+        state.tokens = (state.tokens + gained).min(self.burst);
+        state.last_refill = now;
+    }
+}
+
+impl Sink for Outbox {
+    fn send(&self, kind: MessageKind, target: &str, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            ref mut queues,
+            ref mut turns,
+            ..
+        } = *state;
+        let queue = queues.entry(target.to_owned()).or_insert_with(|| {
+            turns.push_back(target.to_owned());
+            VecDeque::new()
+        });
+        if queue.len() >= self.max_queue {
+            eprintln!("Outgoing queue for {} is full, dropping: {}", target, text);
+            return;
+        }
+        queue.push_back(Outgoing {
+            kind,
+            target: target.to_owned(),
+            text: text.to_owned(),
+        });
+        self.queued.notify_one();
+    }
+}
+
+impl State {
+    /// Pop the next message from the target whose turn it is.
+    fn pop(&mut self) -> Option<Outgoing> {
+        let target = self.turns.pop_front()?;
+        let msg;
+        let exhausted;
+        {
+            let queue = self.queues.get_mut(&target)?;
+            msg = queue.pop_front();
+            exhausted = queue.is_empty();
+        }
+        if exhausted {
+            self.queues.remove(&target);
+        } else {
+            self.turns.push_back(target);
+        }
+        msg
+    }
+}
+
+#[test]
+fn test_round_robin_and_cap() {
+    let outbox = Outbox::new(&Flood {
+        burst: 100,
+        interval_ms: 1,
+        max_queue: 3,
+    });
+    outbox.set_connected(true);
+    for i in 0..5 {
+        outbox.send(MessageKind::Privmsg, "#flood", &i.to_string());
+    }
+    outbox.send(MessageKind::Privmsg, "#calm", "hi");
+    let mut sent = Vec::new();
+    for _ in 0..4 {
+        let msg = outbox.next();
+        sent.push(format!("{} {}", msg.target, msg.text));
+    }
+    assert_eq!(sent, ["#flood 0", "#calm hi", "#flood 1", "#flood 2"]);
+    assert!(outbox.state.lock().unwrap().pop().is_none());
+}
+
+#[test]
+fn test_disconnected() {
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    let outbox = Arc::new(Outbox::new(&Flood {
+        burst: 100,
+        interval_ms: 1,
+        max_queue: 3,
+    }));
+    outbox.send(MessageKind::Privmsg, "#chan", "second");
+    outbox.send(MessageKind::Privmsg, "#other", "third");
+    outbox.put_back(Outgoing {
+        kind: MessageKind::Privmsg,
+        target: "#chan".into(),
+        text: "first".into(),
+    });
+    let (tx, rx) = mpsc::channel();
+    thread::spawn({
+        let outbox = Arc::clone(&outbox);
+        move || loop {
+            tx.send(outbox.next().text).unwrap();
+        }
+    });
+    // Held back until there's a connection
+    assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    outbox.set_connected(true);
+    let sent: Vec<String> = (0..3)
+        .map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap())
+        .collect();
+    assert_eq!(sent, ["first", "third", "second"]);
+}
@@ -0,0 +1,133 @@
+//! Command pipelines, like `.w rust | shl`.
+//!
+//! Every command of a pipeline gets the output of the previous one as its last free argument.
+//! Only the output of the last command is actually sent.
+
+use crate::plugin_container::{lock_plugin, PluginRef};
+use plugin_api::optparse::{self, OptDef, ParseError};
+use plugin_api::{CommandError, CommandFn, Context, MessageKind, Sink, Target};
+use std::sync::Mutex;
+
+/// Maximum number of commands in a pipeline.
+pub(crate) const MAX_STAGES: usize = 4;
+/// Maximum number of bytes of output that gets passed from one command to the next.
+const MAX_STAGE_OUTPUT: usize = 2000;
+
+/// A command of a pipeline, along with the plugin that provides it.
+pub(crate) struct Stage {
+    pub name: &'static str,
+    pub plugin: PluginRef,
+    pub fun: CommandFn,
+    pub opts: Vec<OptDef>,
+    /// The arguments that were given to the command in the pipeline itself.
+    pub args: String,
+}
+
+/// Why a pipeline stopped.
+pub(crate) enum StageError {
+    /// The arguments of a command couldn't be parsed.
+    Opts(ParseError),
+    /// A command failed. Contains the name of the command.
+    ///
+    /// The names are copied, because the plugin may be unloaded by the time the error is
+    /// reported.
+    Command(String, CommandError),
+    /// A command panicked. Contains the name of the command and the panic message.
+    Panic(String, String),
+}
+
+/// Split a command line into the commands of the pipeline. They are separated by ` | `.
+pub(crate) fn split(command: &str) -> Vec<&str> {
+    // Not every `|`, so that it can still be used in arguments
+    command.split(" | ").map(str::trim).collect()
+}
+
+/// Run the commands of a pipeline one after the other. The output of the last one goes to `sink`.
+pub(crate) fn run(
+    stages: Vec<Stage>,
+    sink: &Sink,
+    target: Target,
+    sender: &str,
+) -> Result<(), StageError> {
+    let mut input = None;
+    let last = stages.len() - 1;
+    for (i, stage) in stages.into_iter().enumerate() {
+        let opts =
+            optparse::parse_command(&stage.args, input.as_ref().map(String::as_str), &stage.opts)
+                .map_err(StageError::Opts)?;
+        let capture = Capture::new(sender);
+        let stage_sink: &Sink = if i == last { sink } else { &capture };
+        let ctx = Context::new(
+            stage_sink,
+            target,
+            sender,
+            &stage.plugin.storage,
+            &*stage.plugin.schedule,
+        );
+        let result = stage
+            .plugin
+            .health
+            .guard(&format!("command '{}'", stage.name), || {
+                (stage.fun)(&mut *lock_plugin(&stage.plugin.plugin), opts, ctx)
+            })
+            .map_err(|msg| StageError::Panic(stage.name.to_owned(), msg))?;
+        result.map_err(|e| StageError::Command(stage.name.to_owned(), e))?;
+        input = Some(capture.text.into_inner().unwrap());
+    }
+    Ok(())
+}
+
+/// Collects the output of a command, so it can be fed to the next one.
+struct Capture {
+    /// Replies are prefixed with the nick of the sender, which we don't want to pass on.
+    reply_prefix: String,
+    text: Mutex<String>,
+}
+
+impl Capture {
+    fn new(sender: &str) -> Self {
+        Self {
+            reply_prefix: format!("{}: ", sender),
+            text: Mutex::new(String::new()),
+        }
+    }
+}
+
+impl Sink for Capture {
+    fn send(&self, _kind: MessageKind, _target: &str, text: &str) {
+        let text = if text.starts_with(&self.reply_prefix) {
+            &text[self.reply_prefix.len()..]
+        } else {
+            text
+        };
+        let mut buf = self.text.lock().unwrap();
+        if !buf.is_empty() {
+            buf.push(' ');
+        }
+        for ch in text.chars() {
+            if buf.len() + ch.len_utf8() > MAX_STAGE_OUTPUT {
+                break;
+            }
+            buf.push(ch);
+        }
+    }
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(
+        split(".w rust | shl  |  permut"),
+        [".w rust", "shl", "permut"]
+    );
+    assert_eq!(split(".tell bob a|b"), [".tell bob a|b"]);
+}
+
+#[test]
+fn test_capture() {
+    let capture = Capture::new("alice");
+    capture.send(MessageKind::Privmsg, "#chan", "alice: Rust is a language");
+    capture.send(MessageKind::Action, "#chan", "rusts");
+    assert_eq!(*capture.text.lock().unwrap(), "Rust is a language rusts");
+    capture.send(MessageKind::Privmsg, "#chan", &"ä".repeat(MAX_STAGE_OUTPUT));
+    assert!(capture.text.lock().unwrap().len() <= MAX_STAGE_OUTPUT);
+}
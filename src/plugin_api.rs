@@ -3,65 +3,297 @@
 #[macro_use]
 extern crate downcast_rs;
 pub extern crate hiirc;
+pub extern crate toml;
 
 use crate::util::SplitChunks;
 use downcast_rs::Downcast;
+use std::error::Error;
+use std::time::Duration;
 
 /// The most commonly used types when implementing a plugin.
 pub mod prelude {
     pub use super::{
         optparse::{Opt, ParsedOpts},
-        Command, Context, Plugin, PluginMeta,
+        storage::Storage,
+        Command, CommandError, CommandResult, Context, MessageKind, NetworkContext, Plugin,
+        PluginMeta, Role, ScheduleId, Scheduler, Sink, Target, Timer,
     };
     pub use hiirc::IrcWrite;
 }
 
 pub mod optparse;
+pub mod schedule;
+pub mod storage;
+pub mod testing;
 mod util;
 
 use crate::optparse::OptDef;
 use crate::prelude::*;
 
+/// Where an event happened, and thus where replies should go.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target<'a> {
+    /// A channel, by name.
+    Channel(&'a str),
+    /// A private message (query) with a user, by nickname.
+    User(&'a str),
+}
+
+impl<'a> Target<'a> {
+    /// The name of the channel or the nickname of the user.
+    pub fn name(&self) -> &'a str {
+        match *self {
+            Target::Channel(name) | Target::User(name) => name,
+        }
+    }
+}
+
+/// The kinds of messages that can be sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    Privmsg,
+    Notice,
+    /// CTCP ACTION, also known as /me
+    Action,
+}
+
+/// Where outgoing messages go.
+///
+/// The core implements this with its flood-controlled outgoing message queue.
+pub trait Sink: Send + Sync {
+    /// Send a single message. It must fit into one IRC line.
+    fn send(&self, kind: MessageKind, target: &str, text: &str);
+    /// Send a message of any length, split into as many lines as needed.
+    fn send_chunked(&self, kind: MessageKind, target: &str, msg: &str) {
+        // Even though IRC protocol message length limit is 512,
+        // freenode seems to cut off messages starting after about 400 characters.
+        for chunk in SplitChunks::new(msg, 400) {
+            let chunk = chunk.trim();
+            if !chunk.is_empty() {
+                self.send(kind, target, chunk);
+            }
+        }
+    }
+}
+
+/// Identifies a scheduled call, so it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ScheduleId(pub u64);
+
+/// Type of the function that gets called when a scheduled call is due.
+///
+/// The `&str` is the data that was given when scheduling.
+pub type ScheduledFn = fn(&mut Plugin, &str, NetworkContext);
+
+/// Calls plugin functions later on, e.g. for reminders.
+///
+/// The core implements this. Scheduled calls are cancelled when the plugin is unloaded or
+/// reloaded, so anything that should survive that has to be kept in storage.
+pub trait Scheduler: Send + Sync {
+    /// Call `fun` with `data` after `delay`.
+    fn schedule(&self, delay: Duration, fun: ScheduledFn, data: String) -> ScheduleId;
+    /// Cancel a scheduled call. Returns false if it was already made or cancelled.
+    fn cancel(&self, id: ScheduleId) -> bool;
+}
+
 /// IRC context.
 #[derive(Clone, Copy)]
 pub struct Context<'a> {
-    /// The hiirc Irc handle through which you can send commands and stuff.
-    pub irc: &'a hiirc::Irc,
-    /// The channel that the event happened on.
-    pub channel: &'a hiirc::Channel,
-    /// The user that caused the event.
-    pub sender: &'a hiirc::ChannelUser,
+    /// Where the messages sent through this context go.
+    pub sink: &'a Sink,
+    /// The channel or query that the event happened on.
+    pub target: Target<'a>,
+    /// The nickname of the user that caused the event.
+    pub sender: &'a str,
+    /// Persistent storage of the plugin.
+    pub storage: &'a Storage,
+    /// For calling the plugin back later.
+    pub scheduler: &'a Scheduler,
 }
 
 impl<'a> Context<'a> {
     /// JUST DO IT.
     pub fn new(
-        irc: &'a hiirc::Irc,
-        channel: &'a hiirc::Channel,
-        sender: &'a hiirc::ChannelUser,
+        sink: &'a Sink,
+        target: Target<'a>,
+        sender: &'a str,
+        storage: &'a Storage,
+        scheduler: &'a Scheduler,
     ) -> Self {
         Self {
-            irc,
-            channel,
+            sink,
+            target,
             sender,
+            storage,
+            scheduler,
         }
     }
     /// Send a message to the channel belonging to this context.
+    ///
+    /// If the event came from a private message, the message is sent back to the sender.
     pub fn send_channel(&self, msg: &str) {
-        // Even though IRC protocol message length limit is 512,
-        // freenode seems to cut off messages starting after about 400 characters.
-        for chunk in SplitChunks::new(msg, 400) {
-            let chunk = chunk.trim();
-            if !chunk.is_empty() {
-                let _ = self.irc.privmsg(self.channel.name(), chunk);
-            }
-            std::thread::sleep(std::time::Duration::from_millis(500));
+        self.sink
+            .send_chunked(MessageKind::Privmsg, self.target.name(), msg);
+    }
+    /// Reply to the sender, prefixing the message with their nick.
+    ///
+    /// In private messages, there is no need for the prefix, so it's left out.
+    pub fn reply(&self, msg: &str) {
+        match self.target {
+            Target::Channel(_) => self.send_channel(&format!("{}: {}", self.sender, msg)),
+            Target::User(_) => self.send_channel(msg),
         }
     }
+    /// Send a notice to the sender.
+    pub fn notice(&self, msg: &str) {
+        self.sink
+            .send_chunked(MessageKind::Notice, self.sender, msg);
+    }
+    /// Send a CTCP ACTION (/me) to the channel belonging to this context.
+    pub fn action(&self, msg: &str) {
+        self.sink
+            .send_chunked(MessageKind::Action, self.target.name(), msg);
+    }
+    /// Send a message to some other channel or user.
+    pub fn send_to(&self, target: &str, msg: &str) {
+        self.sink.send_chunked(MessageKind::Privmsg, target, msg);
+    }
+}
+
+/// IRC context for events that didn't happen on any particular channel or query,
+/// like quits and nick changes.
+#[derive(Clone, Copy)]
+pub struct NetworkContext<'a> {
+    /// Where the messages sent through this context go.
+    pub sink: &'a Sink,
+    /// Persistent storage of the plugin.
+    pub storage: &'a Storage,
+    /// For calling the plugin back later.
+    pub scheduler: &'a Scheduler,
+}
+
+impl<'a> NetworkContext<'a> {
+    pub fn new(sink: &'a Sink, storage: &'a Storage, scheduler: &'a Scheduler) -> Self {
+        Self {
+            sink,
+            storage,
+            scheduler,
+        }
+    }
+    /// Send a message to a channel or user.
+    pub fn send_to(&self, target: &str, msg: &str) {
+        self.sink.send_chunked(MessageKind::Privmsg, target, msg);
+    }
 }
 
 /// Type of the function that gets called when a command is invoked.
-pub type CommandFn = fn(&mut Plugin, ParsedOpts, Context);
+pub type CommandFn = fn(&mut Plugin, ParsedOpts, Context) -> CommandResult;
+
+/// The result of invoking a command.
+pub type CommandResult = Result<(), CommandError>;
+
+/// Error returned by a failed command.
+///
+/// The core reports it to the user, tagged with the name of the command,
+/// and logs the whole chain of causes.
+#[derive(Debug)]
+pub struct CommandError {
+    msg: String,
+    /// The error that caused this, followed by what caused that, and so on.
+    causes: Vec<String>,
+}
+
+impl CommandError {
+    /// An error with just a message.
+    pub fn new<S: Into<String>>(msg: S) -> Self {
+        Self {
+            msg: msg.into(),
+            causes: Vec::new(),
+        }
+    }
+    /// An error caused by another error. `msg` should say what was being done.
+    pub fn with_cause<S: Into<String>, E: Into<Box<Error>>>(msg: S, cause: E) -> Self {
+        let cause = cause.into();
+        let mut causes = vec![cause.to_string()];
+        causes.extend(source_chain(&*cause));
+        Self {
+            msg: msg.into(),
+            causes,
+        }
+    }
+    /// The message describing the failure.
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+    /// The chain of causes of this error, starting with the direct cause.
+    pub fn causes(&self) -> &[String] {
+        &self.causes
+    }
+}
+
+/// The descriptions of the sources of `e`, recursively.
+fn source_chain(e: &Error) -> Vec<String> {
+    let mut chain = Vec::new();
+    let mut source = e.source();
+    while let Some(e) = source {
+        chain.push(e.to_string());
+        source = e.source();
+    }
+    chain
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.causes.first() {
+            Some(cause) => write!(f, "{}: {}", self.msg, cause),
+            None => f.write_str(&self.msg),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+impl<'a> From<&'a str> for CommandError {
+    fn from(msg: &'a str) -> Self {
+        Self::new(msg)
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        Self::new(msg)
+    }
+}
+
+impl From<Box<Error>> for CommandError {
+    fn from(e: Box<Error>) -> Self {
+        Self {
+            msg: e.to_string(),
+            causes: source_chain(&*e),
+        }
+    }
+}
+
+/// How much the bot trusts a user. Higher roles can do everything lower roles can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Everyone,
+    Trusted,
+    Admin,
+    Owner,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match *self {
+            Role::Everyone => "everyone",
+            Role::Trusted => "trusted",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        };
+        f.write_str(name)
+    }
+}
 
 /// A command that can be invoked by a user.
 pub struct Command {
@@ -72,6 +304,8 @@ pub struct Command {
     /// The function that gets called when the command is invoked.
     pub fun: CommandFn,
     pub opts: Vec<OptDef>,
+    /// The role a user needs to invoke this command.
+    pub role: Role,
 }
 
 impl Command {
@@ -81,8 +315,14 @@ impl Command {
             help,
             fun,
             opts: Vec::new(),
+            role: Role::Everyone,
         }
     }
+    /// Only allow users with at least `role` to invoke this command.
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
     pub fn opt(
         mut self,
         short: char,
@@ -100,11 +340,48 @@ impl Command {
     }
 }
 
+/// Type of the function that gets called when a timer fires.
+pub type TimerFn = fn(&mut Plugin, NetworkContext);
+
+/// A timer that calls a function after some time has passed.
+///
+/// Timers are registered along with the plugin. For calls that depend on what happens later,
+/// like reminders, use the `scheduler` of the contexts instead.
+pub struct Timer {
+    /// How long to wait after the plugin was loaded before firing for the first time.
+    pub delay: Duration,
+    /// If this is `Some`, the timer keeps firing with this interval.
+    pub interval: Option<Duration>,
+    /// The function that gets called when the timer fires.
+    pub fun: TimerFn,
+}
+
+impl Timer {
+    /// A timer that fires only once, after `delay`.
+    pub fn once(delay: Duration, fun: TimerFn) -> Self {
+        Self {
+            delay,
+            interval: None,
+            fun,
+        }
+    }
+    /// A timer that fires every `interval`.
+    pub fn repeating(interval: Duration, fun: TimerFn) -> Self {
+        Self {
+            delay: interval,
+            interval: Some(interval),
+            fun,
+        }
+    }
+}
+
 /// Metadata for a plugin.
 #[derive(Default)]
 pub struct PluginMeta {
     /// The commands that this plugin has.
     pub commands: Vec<Command>,
+    /// The timers that this plugin has.
+    pub timers: Vec<Timer>,
 }
 
 impl PluginMeta {
@@ -116,22 +393,81 @@ impl PluginMeta {
     pub fn add_simple_command(&mut self, name: &'static str, help: &'static str, fun: CommandFn) {
         self.commands.push(Command::new(name, help, fun));
     }
+    /// Add a timer.
+    pub fn add_timer(&mut self, timer: Timer) {
+        self.timers.push(timer);
+    }
 }
 
 /// Every plugin must implement this trait.
 pub trait Plugin: Send + Downcast {
     /// Executed when a message is sent to a channel.
     fn channel_msg(&mut self, _msg: &str, _ctx: Context) {}
+    /// Executed when a private message is sent to the bot.
+    fn private_msg(&mut self, _msg: &str, _ctx: Context) {}
+    /// Executed when a user joins a channel. `ctx.sender` is the user who joined.
+    fn user_join(&mut self, _ctx: Context) {}
+    /// Executed when a user leaves a channel. `ctx.sender` is the user who left.
+    fn user_part(&mut self, _reason: Option<&str>, _ctx: Context) {}
+    /// Executed when a user is kicked from a channel. `ctx.sender` is the kicker.
+    fn user_kick(&mut self, _kicked: &str, _reason: Option<&str>, _ctx: Context) {}
+    /// Executed when a user disconnects from the network.
+    fn user_quit(&mut self, _nick: &str, _reason: Option<&str>, _ctx: NetworkContext) {}
+    /// Executed when a user changes their nickname.
+    fn nick_change(&mut self, _old: &str, _new: &str, _ctx: NetworkContext) {}
+    /// Executed when the topic of a channel is changed. `ctx.sender` is who changed it.
+    fn topic_change(&mut self, _topic: &str, _ctx: Context) {}
     /// Every plugin must be constructible without arguments.
     fn new() -> Self
     where
         Self: Sized;
+    /// Configure the plugin using its `[plugins.<name>]` section of the configuration.
+    ///
+    /// This is called after construction, and whenever `reload-cfg` changes the section.
+    /// `cfg` is always a table, and it's empty if the section is missing.
+    /// For typed configuration, deserialize it using `cfg.clone().try_into()`.
+    fn configure(&mut self, _cfg: &toml::Value) -> Result<(), Box<Error>> {
+        Ok(())
+    }
     /// Register stuff for this plugin. For example, commands.
     fn register(&self, _meta: &mut PluginMeta) {}
+    /// Save in-memory state right before the plugin gets reloaded.
+    ///
+    /// The returned bytes are handed to `restore_state` of the freshly loaded instance.
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Restore state that was saved by `save_state` of the previous instance.
+    ///
+    /// If the state is incompatible (e.g. the format changed between versions), return an error.
+    /// The core then discards this instance and uses a freshly constructed one.
+    fn restore_state(&mut self, _state: &[u8]) -> Result<(), Box<Error>> {
+        Ok(())
+    }
 }
 
 impl_downcast!(Plugin);
 
+/// Type of `catch_panic`.
+pub type CatchPanicFn = fn(&mut FnMut()) -> Result<(), String>;
+
+/// Call `f`, catching a panic. Returns the panic message if there was one.
+///
+/// Every plugin has its own copy of std, and a panic can only be caught by the copy that
+/// raised it. So the core calls into plugins through the copy of this function that
+/// `plugin_export!` compiles into the plugin.
+pub fn catch_panic(f: &mut FnMut()) -> Result<(), String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "Box<Any>".to_owned()
+        }
+    })
+}
+
 /// Declare a type to be the plugin.
 ///
 /// Only one type per crate can be the plugin.
@@ -143,5 +479,9 @@ macro_rules! plugin_export {
         pub fn init() -> Arc<Mutex<Plugin>> {
             Arc::new(Mutex::new($plugin::new()))
         }
+        #[no_mangle]
+        pub fn catch_panic(f: &mut FnMut()) -> Result<(), String> {
+            $crate::catch_panic(f)
+        }
     };
 }
@@ -1,44 +1,218 @@
+use crate::config::Config;
 use libloading::Library;
-use plugin_api::{Plugin, PluginMeta};
+use plugin_api::schedule::Schedule;
+use plugin_api::storage::Storage;
+use plugin_api::{CatchPanicFn, Plugin, PluginMeta};
 use std::error::Error;
+use std::fmt;
 use std::mem::ManuallyDrop;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many times a plugin can crash before it's disabled until it's reloaded.
+const MAX_CRASHES: usize = 3;
+/// How long the core waits for a busy plugin while everything else waits for the core.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct PluginContainer {
     pub plugin: ManuallyDrop<Arc<Mutex<Plugin>>>,
     pub meta: ManuallyDrop<PluginMeta>,
-    lib: ManuallyDrop<Library>,
+    pub storage: Storage,
+    pub health: Health,
+    /// The calls the plugin scheduled while running.
+    pub schedule: Arc<Schedule>,
+    lib: ManuallyDrop<Arc<Library>>,
+}
+
+/// What a job needs to call into a plugin.
+///
+/// It keeps the library of the plugin loaded, so the plugin can be unloaded or reloaded while
+/// jobs for it are still queued, or stuck past their deadline.
+#[derive(Clone)]
+pub struct PluginRef {
+    pub plugin: Arc<Mutex<Plugin>>,
+    pub storage: Storage,
+    pub health: Health,
+    pub schedule: Arc<Schedule>,
+    // Last, so the library is closed only after everything else is dropped
+    _lib: Arc<Library>,
 }
 
 impl PluginContainer {
-    pub fn load(name: &str) -> Result<Self, Box<Error>> {
-        use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
-        #[cfg(debug_assertions)]
-        let root = "target/debug";
-        #[cfg(not(debug_assertions))]
-        let root = "target/release";
-        let path = format!(
-            "{dir}/{prefix}{name}{suffix}",
-            dir = root,
-            prefix = DLL_PREFIX,
-            name = name,
-            suffix = DLL_SUFFIX
-        );
-        let lib = Library::new(path)?;
+    pub fn load(name: &str, config: &Config, storage: Storage) -> Result<Self, Box<Error>> {
+        let lib = open_library(name, config)?;
+        let catch = unsafe { *lib.get::<CatchPanicFn>(b"catch_panic")? };
+        let health = Health::new(name, catch);
         let plugin = {
             let init = unsafe { lib.get::<fn() -> Arc<Mutex<Plugin>>>(b"init")? };
-            init()
+            health.guard("init", || init())?
         };
+        let settings = config.plugin_settings(name);
+        health.guard("configure", || lock_plugin(&plugin).configure(&settings))??;
         let mut meta = PluginMeta::default();
-        plugin.lock().unwrap().register(&mut meta);
+        health.guard("register", || lock_plugin(&plugin).register(&mut meta))?;
         Ok(Self {
             plugin: ManuallyDrop::new(plugin),
             meta: ManuallyDrop::new(meta),
-            lib: ManuallyDrop::new(lib),
+            storage,
+            health,
+            schedule: Arc::new(Schedule::default()),
+            lib: ManuallyDrop::new(Arc::new(lib)),
         })
     }
+    pub fn handle(&self) -> PluginRef {
+        PluginRef {
+            plugin: Arc::clone(&self.plugin),
+            storage: self.storage.clone(),
+            health: self.health.clone(),
+            schedule: Arc::clone(&self.schedule),
+            _lib: Arc::clone(&self.lib),
+        }
+    }
+    /// Whether a job still holds on to the plugin, so its library can't be closed yet.
+    pub fn in_use(&self) -> bool {
+        Arc::strong_count(&self.lib) > 1
+    }
+}
+
+/// Lock a plugin, even if a panic poisoned its mutex.
+pub fn lock_plugin(plugin: &Mutex<Plugin>) -> MutexGuard<Plugin> {
+    plugin.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lock a plugin, unless it stays busy for longer than `timeout`.
+///
+/// For when the core is locked, so a plugin stuck in a slow command can't hold up the bot.
+pub fn try_lock_plugin(plugin: &Mutex<Plugin>, timeout: Duration) -> Option<MutexGuard<Plugin>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match plugin.try_lock() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(e)) => return Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => return None,
+        }
+    }
 }
 
+/// Keeps count of the crashes of a plugin. The clones share the count.
+#[derive(Clone)]
+pub struct Health {
+    name: Arc<String>,
+    crashes: Arc<AtomicUsize>,
+    /// The `catch_panic` of the plugin's library.
+    catch: CatchPanicFn,
+}
+
+impl Health {
+    fn new(name: &str, catch: CatchPanicFn) -> Self {
+        Self {
+            name: Arc::new(name.to_owned()),
+            crashes: Arc::new(AtomicUsize::new(0)),
+            catch,
+        }
+    }
+    /// Whether the plugin crashed so many times that it's disabled.
+    pub fn is_disabled(&self) -> bool {
+        self.crashes.load(Ordering::SeqCst) >= MAX_CRASHES
+    }
+    /// The name of the plugin.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Call into the plugin, catching a panic. A panic counts as a crash, and its message is
+    /// returned as the error. `what` is logged along with the panic.
+    ///
+    /// The panic is caught inside the plugin, see `plugin_api::catch_panic`.
+    pub fn guard<R, F: FnOnce() -> R>(&self, what: &str, f: F) -> Result<R, String> {
+        let mut f = Some(f);
+        let mut result = None;
+        (self.catch)(&mut || result = f.take().map(|f| f())).map_err(|msg| {
+            eprintln!("Plugin '{}' panicked in {}: {}", self.name, what, msg);
+            if self.crashes.fetch_add(1, Ordering::SeqCst) + 1 == MAX_CRASHES {
+                eprintln!(
+                    "Plugin '{}' crashed {} times, disabling it until it's reloaded",
+                    self.name, MAX_CRASHES
+                );
+            }
+            msg
+        })?;
+        Ok(result.unwrap())
+    }
+}
+
+/// Open the library of a plugin from the first candidate path that works.
+fn open_library(name: &str, config: &Config) -> Result<Library, LoadError> {
+    let mut attempts = Vec::new();
+    for path in candidate_paths(name, config) {
+        match Library::new(&path) {
+            Ok(lib) => return Ok(lib),
+            Err(e) => attempts.push((path, e.to_string())),
+        }
+    }
+    Err(LoadError {
+        name: name.to_owned(),
+        attempts,
+    })
+}
+
+/// The paths to look for the library of a plugin at, in order.
+///
+/// If the plugin has an explicit `path`, that's the only candidate. Otherwise the library is
+/// looked for in each of the plugin directories, or `target/{debug or release}` if there
+/// are none configured.
+fn candidate_paths(name: &str, config: &Config) -> Vec<PathBuf> {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+    if let Some(path) = config.plugins.get(name).and_then(|p| p.path.as_ref()) {
+        return vec![PathBuf::from(path)];
+    }
+    #[cfg(debug_assertions)]
+    let root = "target/debug";
+    #[cfg(not(debug_assertions))]
+    let root = "target/release";
+    let file_name = format!(
+        "{prefix}{name}{suffix}",
+        prefix = DLL_PREFIX,
+        name = name,
+        suffix = DLL_SUFFIX
+    );
+    if config.bot.plugin_dirs.is_empty() {
+        vec![Path::new(root).join(file_name)]
+    } else {
+        config
+            .bot
+            .plugin_dirs
+            .iter()
+            .map(|dir| Path::new(dir).join(&file_name))
+            .collect()
+    }
+}
+
+/// None of the candidate paths of a plugin could be loaded.
+#[derive(Debug)]
+pub struct LoadError {
+    name: String,
+    /// The paths that were tried, along with the reasons they failed.
+    attempts: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't load plugin \"{}\". Tried:", self.name)?;
+        for (path, e) in &self.attempts {
+            write!(f, "\n  {}: {}", path.display(), e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LoadError {}
+
 impl Drop for PluginContainer {
     fn drop(&mut self) {
         unsafe {
@@ -46,8 +220,21 @@ impl Drop for PluginContainer {
             ManuallyDrop::drop(&mut self.plugin);
             // Drop meta, it depends on lib
             ManuallyDrop::drop(&mut self.meta);
-            // Finally drop the lib
+            // Finally drop the lib, which is closed once no job refers to it anymore
             ManuallyDrop::drop(&mut self.lib);
         }
     }
 }
+
+#[test]
+fn test_health() {
+    // The plugin's copy of `catch_panic` would be used for a real plugin
+    let health = Health::new("crashy", plugin_api::catch_panic);
+    assert_eq!(health.guard("test", || 42), Ok(42));
+    for _ in 0..MAX_CRASHES {
+        assert!(!health.is_disabled());
+        let result = health.clone().guard("test", || panic!("oh no"));
+        assert_eq!(result, Err::<(), _>("oh no".to_owned()));
+    }
+    assert!(health.is_disabled());
+}
@@ -0,0 +1,223 @@
+//! Local proxy between hiirc and the IRC server.
+//!
+//! hiirc can only connect over plain TCP, and registers right away. Connecting through the
+//! proxy instead lets the bot talk TLS to the server, and start capability negotiation before
+//! hiirc registers.
+
+use crate::config;
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::error::Error;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long forwarding waits for data from one side before checking the other.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long an opened proxy waits for hiirc to connect to it.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Forwards connections to the server.
+pub(crate) struct Proxy {
+    connector: Option<TlsConnector>,
+    host: String,
+    port: u16,
+    /// Sent to the server before anything else on each connection.
+    greeting: &'static str,
+}
+
+impl Proxy {
+    pub fn new(server: &config::Server, greeting: &'static str) -> Result<Self, Box<Error>> {
+        let connector = if server.tls {
+            Some(connector(server)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            connector,
+            host: server.url.clone(),
+            port: server.port(),
+            greeting,
+        })
+    }
+    /// Listen for a single connection, forward it to the server, and return the local address
+    /// to connect to.
+    ///
+    /// The listener is closed as soon as the connection is made, or when nothing connects in
+    /// time. Otherwise any local user could connect through it, and be logged in as the bot
+    /// with its client certificate.
+    pub fn open(&self) -> Result<SocketAddr, Box<Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let connector = self.connector.clone();
+        let host = self.host.clone();
+        let port = self.port;
+        let greeting = self.greeting;
+        thread::spawn(move || {
+            let result = accept_one(listener)
+                .map_err(Box::from)
+                .and_then(|local| connect(local, connector.as_ref(), &host, port, greeting));
+            if let Err(e) = result {
+                eprintln!("Connection to {}:{} failed: {}", host, port, e);
+            }
+        });
+        Ok(addr)
+    }
+}
+
+/// Accept one connection within `ACCEPT_TIMEOUT`, and close the listener.
+fn accept_one(listener: TcpListener) -> io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + ACCEPT_TIMEOUT;
+    loop {
+        match listener.accept() {
+            Ok((local, _)) => {
+                local.set_nonblocking(false)?;
+                return Ok(local);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "nothing connected to the proxy",
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn connector(server: &config::Server) -> Result<TlsConnector, Box<Error>> {
+    let mut builder = TlsConnector::builder();
+    if !server.verify_certificate {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    if let Some(ref path) = server.ca_file {
+        let pem = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let cert = Certificate::from_pem(&pem).map_err(|e| format!("{}: {}", path, e))?;
+        builder.add_root_certificate(cert);
+    }
+    if let Some(ref path) = server.client_cert {
+        let der = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let identity = Identity::from_pkcs12(&der, &server.client_cert_password)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        builder.identity(identity);
+    }
+    Ok(builder.build()?)
+}
+
+/// Connect to the server, and forward `local` to it.
+fn connect(
+    local: TcpStream,
+    connector: Option<&TlsConnector>,
+    host: &str,
+    port: u16,
+    greeting: &str,
+) -> Result<(), Box<Error>> {
+    let tcp = TcpStream::connect((host, port))?;
+    match connector {
+        Some(connector) => {
+            let tls = connector.connect(host, tcp)?;
+            // Only now, so the handshake isn't cut short by the timeout
+            tls.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+            forward(local, tls, greeting)
+        }
+        None => {
+            tcp.set_read_timeout(Some(POLL_INTERVAL))?;
+            forward(local, tcp, greeting)
+        }
+    }
+}
+
+/// Copy data both ways between `local` and `remote` until either side closes.
+///
+/// `remote` has to have a read timeout.
+fn forward<S: Read + Write>(
+    mut local: TcpStream,
+    mut remote: S,
+    greeting: &str,
+) -> Result<(), Box<Error>> {
+    remote.write_all(greeting.as_bytes())?;
+    // A TLS stream can't be split between threads, so poll both sides on this one
+    local.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut buf = [0; 4096];
+    while copy_available(&mut local, &mut remote, &mut buf)?
+        && copy_available(&mut remote, &mut local, &mut buf)?
+    {}
+    Ok(())
+}
+
+/// Copy what `from` has to offer within its read timeout to `to`.
+///
+/// Returns false if `from` has been closed.
+fn copy_available<R: Read, W: Write>(from: &mut R, to: &mut W, buf: &mut [u8]) -> io::Result<bool> {
+    match from.read(buf) {
+        Ok(0) => Ok(false),
+        Ok(n) => to.write_all(&buf[..n]).map(|()| true),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+#[test]
+fn test_proxy() {
+    use native_tls::TlsAcceptor;
+    use std::io::BufReader;
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/");
+    // A stand-in IRC server with a certificate signed by a self-signed CA
+    let identity =
+        Identity::from_pkcs12(&fs::read(dir.to_owned() + "localhost.p12").unwrap(), "test");
+    let acceptor = TlsAcceptor::new(identity.unwrap()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let mut stream = match acceptor.accept(tcp.unwrap()) {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let mut reader = BufReader::new(&mut stream);
+            let mut lines = String::new();
+            reader.read_line(&mut lines).unwrap();
+            reader.read_line(&mut lines).unwrap();
+            stream.write_all(lines.as_bytes()).unwrap();
+        }
+    });
+    let mut server = config::Server {
+        url: "localhost".into(),
+        port: Some(port),
+        tls: true,
+        verify_certificate: true,
+        ca_file: None,
+        client_cert: None,
+        client_cert_password: String::new(),
+    };
+    let talk = |server: &config::Server| {
+        let addr = Proxy::new(server, "CAP LS 302\r\n")
+            .unwrap()
+            .open()
+            .unwrap();
+        let mut local = TcpStream::connect(addr).unwrap();
+        local.write_all(b"NICK bonca\r\n").unwrap();
+        let mut reply = String::new();
+        // A failed connection may get reset, since the proxy didn't read what was sent
+        let _ = BufReader::new(local).read_to_string(&mut reply);
+        // Only the one connection is let through
+        assert!(TcpStream::connect(addr).is_err());
+        reply
+    };
+    // The CA isn't trusted, so the connection gets closed
+    assert_eq!(talk(&server), "");
+    server.ca_file = Some(dir.to_owned() + "ca.pem");
+    assert_eq!(talk(&server), "CAP LS 302\r\nNICK bonca\r\n");
+    server.ca_file = None;
+    server.verify_certificate = false;
+    assert_eq!(talk(&server), "CAP LS 302\r\nNICK bonca\r\n");
+}
@@ -0,0 +1,87 @@
+//! Calls that plugins schedule while they run, like reminders.
+
+use crate::{ScheduleId, ScheduledFn, Scheduler};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A scheduled call that's waiting to be made.
+pub struct Call {
+    pub due: Instant,
+    pub fun: ScheduledFn,
+    pub data: String,
+}
+
+/// The calls a plugin has scheduled.
+///
+/// The core makes the calls when they are due, and closes the schedule when the plugin is
+/// unloaded or reloaded.
+#[derive(Default)]
+pub struct Schedule {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    pending: Vec<(ScheduleId, Call)>,
+    /// Closed schedules drop every call, because the plugin is gone.
+    closed: bool,
+}
+
+impl Schedule {
+    /// Take the calls that are due at `now`, earliest first.
+    pub fn take_due(&self, now: Instant) -> Vec<Call> {
+        let mut state = self.state.lock().unwrap();
+        let (mut due, pending) = state
+            .pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|&(_, ref call)| call.due <= now);
+        state.pending = pending;
+        due.sort_by_key(|&(id, ref call)| (call.due, id.0));
+        due.into_iter().map(|(_, call)| call).collect()
+    }
+    /// Cancel every call, and ignore the ones scheduled from now on.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.closed = true;
+    }
+}
+
+impl Scheduler for Schedule {
+    fn schedule(&self, delay: Duration, fun: ScheduledFn, data: String) -> ScheduleId {
+        let mut state = self.state.lock().unwrap();
+        let id = ScheduleId(state.next_id);
+        state.next_id += 1;
+        if !state.closed {
+            let due = Instant::now() + delay;
+            state.pending.push((id, Call { due, fun, data }));
+        }
+        id
+    }
+    fn cancel(&self, id: ScheduleId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.pending.len();
+        state.pending.retain(|&(other, _)| other != id);
+        state.pending.len() != len
+    }
+}
+
+#[test]
+fn test_schedule() {
+    fn fun(_: &mut crate::Plugin, _: &str, _: crate::NetworkContext) {}
+    let schedule = Schedule::default();
+    let later = schedule.schedule(Duration::from_secs(60), fun, "later".into());
+    schedule.schedule(Duration::from_secs(0), fun, "now".into());
+    let cancelled = schedule.schedule(Duration::from_secs(0), fun, "cancelled".into());
+    assert!(schedule.cancel(cancelled));
+    assert!(!schedule.cancel(cancelled));
+    let due = schedule.take_due(Instant::now());
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].data, "now");
+    assert!(schedule.take_due(Instant::now()).is_empty());
+    schedule.close();
+    assert!(!schedule.cancel(later));
+    schedule.schedule(Duration::from_secs(0), fun, "gone".into());
+    assert!(schedule.take_due(Instant::now()).is_empty());
+}
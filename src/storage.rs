@@ -0,0 +1,174 @@
+//! Persistent key-value storage for plugins.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Magic bytes at the start of every store file.
+const MAGIC: &[u8] = b"BONCAKV1";
+
+/// Persistent key-value store of a plugin.
+///
+/// Every plugin gets its own store, namespaced by the plugin's name.
+/// The whole store is kept in memory, and every modification rewrites the file on disk
+/// by writing a temporary file and renaming it over the old one. This way a crash never
+/// leaves a half-written store behind.
+#[derive(Clone)]
+pub struct Storage {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    map: BTreeMap<String, Vec<u8>>,
+}
+
+impl Storage {
+    /// Open (or create) the store for `namespace` in `dir`.
+    ///
+    /// Handles opened separately would overwrite each other's changes, so open a store once,
+    /// and clone the handle.
+    pub fn open<P: AsRef<Path>>(dir: P, namespace: &str) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(format!("{}.kv", namespace));
+        let map = match File::open(&path) {
+            Ok(mut file) => {
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                decode(&buf)?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner { path, map })),
+        })
+    }
+    /// Get the value belonging to `key`.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().map.get(key).cloned()
+    }
+    /// Set the value of `key`, overwriting the old value, if any.
+    pub fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let old = inner.map.insert(key.to_owned(), value.to_owned());
+        inner.persist().map_err(|e| {
+            match old {
+                Some(old) => inner.map.insert(key.to_owned(), old),
+                None => inner.map.remove(key),
+            };
+            e
+        })
+    }
+    /// Delete `key`. Returns whether there was such a key.
+    pub fn delete(&self, key: &str) -> io::Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.map.remove(key) {
+            Some(old) => inner.persist().map(|()| true).map_err(|e| {
+                inner.map.insert(key.to_owned(), old);
+                e
+            }),
+            None => Ok(false),
+        }
+    }
+    /// All the key-value pairs whose key starts with `prefix`, ordered by key.
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .map
+            .range(prefix.to_owned()..)
+            .take_while(|&(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
+
+impl Inner {
+    fn persist(&self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("kv.tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&encode(&self.map))?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        // Make sure the rename itself hits the disk
+        if let Some(dir) = self.path.parent() {
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode(map: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    for (k, v) in map {
+        write_chunk(&mut buf, k.as_bytes());
+        write_chunk(&mut buf, v);
+    }
+    buf
+}
+
+fn write_chunk(buf: &mut Vec<u8>, chunk: &[u8]) {
+    let len = chunk.len() as u32;
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(chunk);
+}
+
+fn decode(mut buf: &[u8]) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    if !buf.starts_with(MAGIC) {
+        return Err(invalid_data("Not a key-value store"));
+    }
+    buf = &buf[MAGIC.len()..];
+    let mut map = BTreeMap::new();
+    while !buf.is_empty() {
+        let key = read_chunk(&mut buf)?;
+        let key = String::from_utf8(key.to_owned()).map_err(|_| invalid_data("Non-utf8 key"))?;
+        let value = read_chunk(&mut buf)?;
+        map.insert(key, value.to_owned());
+    }
+    Ok(map)
+}
+
+fn read_chunk<'a>(buf: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    if buf.len() < 4 {
+        return Err(invalid_data("Truncated length"));
+    }
+    let mut len = [0; 4];
+    len.copy_from_slice(&buf[..4]);
+    let len = u32::from_le_bytes(len) as usize;
+    if buf.len() < 4 + len {
+        return Err(invalid_data("Truncated chunk"));
+    }
+    let chunk = &buf[4..4 + len];
+    *buf = &buf[4 + len..];
+    Ok(chunk)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn test_storage() {
+    let dir = std::env::temp_dir().join(format!("boncarobot-storage-test-{}", std::process::id()));
+    {
+        let storage = Storage::open(&dir, "test").unwrap();
+        storage.put("karma:alice", b"3").unwrap();
+        storage.put("karma:bob", b"-1").unwrap();
+        storage.put("quote:1", b"hello").unwrap();
+        assert!(storage.delete("quote:1").unwrap());
+        assert!(!storage.delete("quote:1").unwrap());
+    }
+    let storage = Storage::open(&dir, "test").unwrap();
+    assert_eq!(storage.get("karma:alice"), Some(b"3".to_vec()));
+    assert_eq!(storage.get("quote:1"), None);
+    let karma = storage.scan_prefix("karma:");
+    assert_eq!(karma.len(), 2);
+    assert_eq!(karma[1], ("karma:bob".to_owned(), b"-1".to_vec()));
+    fs::remove_dir_all(&dir).unwrap();
+}
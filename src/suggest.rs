@@ -0,0 +1,78 @@
+//! Suggestions for mistyped command names.
+
+use crate::config::Suggestions;
+use distance::damerau_levenshtein;
+
+/// The candidates that are similar enough to `name`, most similar first.
+pub(crate) fn suggest<'a, I>(name: &str, candidates: I, settings: &Suggestions) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let len = name.chars().count();
+    let max_distance = ((len as f64 * settings.max_distance).ceil() as usize).max(1);
+    let mut ranked: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (damerau_levenshtein(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .collect();
+    ranked.sort();
+    ranked.dedup();
+    ranked
+        .into_iter()
+        .take(settings.max)
+        .map(|(_, candidate)| candidate.to_owned())
+        .collect()
+}
+
+/// The reply to an unknown command.
+pub(crate) fn describe(name: &str, suggestions: &[String]) -> String {
+    let mut msg = format!("Unknown command: {}.", name);
+    if let Some((last, rest)) = suggestions.split_last() {
+        msg.push_str(" Did you mean ");
+        for suggestion in rest {
+            msg.push_str(&format!("'{}', ", suggestion));
+        }
+        if !rest.is_empty() {
+            // Replace the trailing comma
+            msg.truncate(msg.len() - 2);
+            msg.push_str(" or ");
+        }
+        msg.push_str(&format!("'{}'?", last));
+    }
+    msg
+}
+
+/// `command` with its name replaced by `suggestion`.
+pub(crate) fn correct(command: &str, suggestion: &str) -> String {
+    let command = command.trim_start();
+    match command.find(char::is_whitespace) {
+        Some(pos) => format!("{}{}", suggestion, &command[pos..]),
+        None => suggestion.to_owned(),
+    }
+}
+
+#[test]
+fn test_suggest() {
+    let settings = Suggestions {
+        quiet: false,
+        max: 2,
+        max_distance: 0.34,
+    };
+    let commands = vec!["search", "shift", "permut", "ud", "w"];
+    assert_eq!(
+        suggest("serch", commands.iter().cloned(), &settings),
+        ["search"]
+    );
+    assert_eq!(
+        suggest("u", commands.iter().cloned(), &settings),
+        ["ud", "w"]
+    );
+    assert!(suggest("hello", commands.iter().cloned(), &settings).is_empty());
+    assert!(suggest("hello", Vec::new(), &settings).is_empty());
+    assert_eq!(describe("x", &[]), "Unknown command: x.");
+    assert_eq!(
+        describe("u", &["w".into(), "ud".into(), "z".into()]),
+        "Unknown command: u. Did you mean 'w', 'ud' or 'z'?"
+    );
+    assert_eq!(correct("serch rust lang", "search"), "search rust lang");
+}
@@ -0,0 +1,157 @@
+//! Testing plugins without a live IRC connection.
+//!
+//! ```ignore
+//! let mut harness = Harness::<TellPlugin>::new();
+//! let replies = harness.invoke("tell bob hi", "alice").unwrap();
+//! assert_eq!(replies[0].text, "alice: I'll pass that on to bob");
+//! ```
+
+use crate::optparse;
+use crate::schedule::Schedule;
+use crate::storage::Storage;
+use crate::{CommandError, Context, MessageKind, NetworkContext, Plugin, PluginMeta, Sink, Target};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A message that was sent through a `Recorder`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sent {
+    pub kind: MessageKind,
+    pub target: String,
+    pub text: String,
+}
+
+/// A `Sink` that records the messages instead of sending them.
+#[derive(Default)]
+pub struct Recorder {
+    sent: Mutex<Vec<Sent>>,
+}
+
+impl Recorder {
+    /// Take the messages recorded so far.
+    pub fn take(&self) -> Vec<Sent> {
+        std::mem::replace(&mut *self.sent.lock().unwrap(), Vec::new())
+    }
+}
+
+impl Sink for Recorder {
+    fn send(&self, kind: MessageKind, target: &str, text: &str) {
+        self.sent.lock().unwrap().push(Sent {
+            kind,
+            target: target.to_owned(),
+            text: text.to_owned(),
+        });
+    }
+}
+
+/// Runs the commands of a plugin the way the bot would.
+///
+/// The plugin gets a fresh storage in a temporary directory, which is removed on drop.
+pub struct Harness<P: Plugin> {
+    pub plugin: P,
+    pub meta: PluginMeta,
+    pub storage: Storage,
+    /// The channel the commands are invoked in.
+    pub channel: String,
+    recorder: Recorder,
+    schedule: Schedule,
+    storage_dir: PathBuf,
+}
+
+impl<P: Plugin> Harness<P> {
+    /// Create the plugin, and register its commands.
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let storage_dir = std::env::temp_dir().join(format!(
+            "boncarobot-harness-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let plugin = P::new();
+        let mut meta = PluginMeta::default();
+        plugin.register(&mut meta);
+        Self {
+            plugin,
+            meta,
+            storage: Storage::open(&storage_dir, "harness").unwrap(),
+            channel: "#test".to_owned(),
+            recorder: Recorder::default(),
+            schedule: Schedule::default(),
+            storage_dir,
+        }
+    }
+    /// Invoke a command in the channel, as `sender`. `cmdline` is without the command prefix,
+    /// e.g. `"tell bob hi"`.
+    ///
+    /// Returns what the command sent.
+    pub fn invoke(&mut self, cmdline: &str, sender: &str) -> Result<Vec<Sent>, CommandError> {
+        let channel = self.channel.clone();
+        self.run(cmdline, sender, Target::Channel(&channel))
+    }
+    /// Invoke a command in a private message from `sender`.
+    pub fn invoke_private(
+        &mut self,
+        cmdline: &str,
+        sender: &str,
+    ) -> Result<Vec<Sent>, CommandError> {
+        self.run(cmdline, sender, Target::User(sender))
+    }
+    /// Send a channel message that isn't a command to the plugin.
+    pub fn channel_msg(&mut self, msg: &str, sender: &str) -> Vec<Sent> {
+        let ctx = Context::new(
+            &self.recorder,
+            Target::Channel(&self.channel),
+            sender,
+            &self.storage,
+            &self.schedule,
+        );
+        self.plugin.channel_msg(msg, ctx);
+        self.recorder.take()
+    }
+    /// Make the calls the plugin scheduled for up to `after` from now, without waiting.
+    ///
+    /// Returns what the calls sent.
+    pub fn run_scheduled(&mut self, after: Duration) -> Vec<Sent> {
+        for call in self.schedule.take_due(Instant::now() + after) {
+            let ctx = NetworkContext::new(&self.recorder, &self.storage, &self.schedule);
+            (call.fun)(&mut self.plugin, &call.data, ctx);
+        }
+        self.recorder.take()
+    }
+    fn run(
+        &mut self,
+        cmdline: &str,
+        sender: &str,
+        target: Target,
+    ) -> Result<Vec<Sent>, CommandError> {
+        let (name, args) = optparse::split_command(cmdline)
+            .ok_or_else(|| CommandError::new("Empty command.".to_owned()))?;
+        let cmd = self
+            .meta
+            .commands
+            .iter()
+            .find(|cmd| cmd.name == name)
+            .ok_or_else(|| CommandError::new(format!("No such command: {}", name)))?;
+        let opts = optparse::parse_command(args, None, &cmd.opts)
+            .map_err(|e| CommandError::new(format!("{:?}", e)))?;
+        let ctx = Context::new(
+            &self.recorder,
+            target,
+            sender,
+            &self.storage,
+            &self.schedule,
+        );
+        let result = (cmd.fun)(&mut self.plugin, opts, ctx);
+        let sent = self.recorder.take();
+        result.map(|()| sent)
+    }
+}
+
+impl<P: Plugin> Drop for Harness<P> {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.storage_dir);
+    }
+}
@@ -0,0 +1,330 @@
+//! Keeping track of who the users are, and what they are allowed to do.
+
+use crate::config::Roles;
+use hiirc::{Code, Message, Prefix};
+use plugin_api::Role;
+use std::collections::HashMap;
+
+/// Tags the replies to our WHOX queries, so they can be told apart from other WHO replies.
+const WHOX_TOKEN: &str = "101";
+
+/// What we know about a user.
+#[derive(Clone, Debug, Default)]
+struct User {
+    /// `nick!user@host`
+    mask: String,
+    /// Services account the user is logged into.
+    ///
+    /// Only known if the server has `extended-join` and `account-notify`, which are requested
+    /// during capability negotiation in `auth`. The accounts of the users that are already in
+    /// a channel when the bot joins it are asked for with WHOX.
+    account: Option<String>,
+    /// The channels we have seen the user in, lowercase.
+    channels: Vec<String>,
+}
+
+/// The users we have seen messages from, keyed by lowercase nick.
+///
+/// A user is forgotten once they no longer share a channel with the bot, because then the bot
+/// wouldn't see them quit, and someone else could take their nick.
+#[derive(Default)]
+pub(crate) struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    /// Learn about the sender of `msg`. `own_nick` is the current nick of the bot.
+    pub fn observe(&mut self, msg: &Message, own_nick: &str) {
+        if let Code::Unknown(ref code) = msg.code {
+            if code == "354" {
+                self.observe_who_reply(msg);
+                return;
+            }
+        }
+        let prefix = match msg.prefix {
+            Some(Prefix::User(ref prefix)) => prefix,
+            _ => return,
+        };
+        let key = prefix.nickname.to_lowercase();
+        let channel = msg
+            .args
+            .get(0)
+            .filter(|arg| arg.starts_with('#') || arg.starts_with('&'))
+            .map(|channel| channel.to_lowercase());
+        match msg.code {
+            Code::Quit => {
+                self.users.remove(&key);
+                return;
+            }
+            Code::Part => {
+                if let Some(channel) = channel {
+                    self.left(&key, &channel, own_nick);
+                }
+                return;
+            }
+            Code::Kick => {
+                if let (Some(channel), Some(nick)) = (channel, msg.args.get(1)) {
+                    self.left(&nick.to_lowercase(), &channel, own_nick);
+                }
+                return;
+            }
+            Code::Nick => {
+                if let Some(new) = msg.args.get(0).or_else(|| msg.suffix.as_ref()) {
+                    if let Some(mut user) = self.users.remove(&key) {
+                        user.mask = format!("{}!{}@{}", new, prefix.username, prefix.hostname);
+                        self.users.insert(new.to_lowercase(), user);
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+        let user = self.seen(
+            &prefix.nickname,
+            &prefix.username,
+            &prefix.hostname,
+            channel,
+        );
+        match msg.code {
+            // extended-join: JOIN #channel account :Real Name
+            Code::Join => {
+                if let Some(account) = msg.args.get(1) {
+                    user.account = parse_account(account);
+                }
+            }
+            // account-notify: ACCOUNT account
+            Code::Unknown(ref cmd) if cmd == "ACCOUNT" => {
+                if let Some(account) = msg.args.get(0).or_else(|| msg.suffix.as_ref()) {
+                    user.account = parse_account(account);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// The WHOX query to send when `msg` is the bot joining a channel, so the accounts of the
+    /// users that are already there become known.
+    pub fn who_query(msg: &Message, own_nick: &str) -> Option<String> {
+        match (&msg.code, &msg.prefix, msg.args.get(0)) {
+            (&Code::Join, &Some(Prefix::User(ref prefix)), Some(channel))
+                if prefix.nickname.eq_ignore_ascii_case(own_nick) =>
+            {
+                Some(format!("WHO {} %tcuhna,{}", channel, WHOX_TOKEN))
+            }
+            _ => None,
+        }
+    }
+    /// Learn about a user from a reply to our WHOX query.
+    fn observe_who_reply(&mut self, msg: &Message) {
+        // me token channel user host nick account, in that order regardless of the query
+        let args = &msg.args;
+        if args.len() < 6 || args[1] != WHOX_TOKEN {
+            return;
+        }
+        let account = match args.get(6).or_else(|| msg.suffix.as_ref()) {
+            Some(account) => account,
+            None => return,
+        };
+        let channel = args[2].to_lowercase();
+        let user = self.seen(&args[5], &args[3], &args[4], Some(channel));
+        // `0` means not logged in
+        user.account = if account == "0" {
+            None
+        } else {
+            Some(account.clone())
+        };
+    }
+    /// The user `nick!username@hostname` was seen, in `channel` if it's a channel message.
+    fn seen(
+        &mut self,
+        nick: &str,
+        username: &str,
+        hostname: &str,
+        channel: Option<String>,
+    ) -> &mut User {
+        let user = self
+            .users
+            .entry(nick.to_lowercase())
+            .or_insert_with(User::default);
+        let user_host = format!("{}@{}", username, hostname);
+        if !user.mask.ends_with(&format!("!{}", user_host)) {
+            // Someone else took the nick, or the user logged out and changed their host
+            *user = User::default();
+        }
+        user.mask = format!("{}!{}", nick, user_host);
+        if let Some(channel) = channel {
+            if !user.channels.contains(&channel) {
+                user.channels.push(channel);
+            }
+        }
+        user
+    }
+    /// `nick` left `channel`. Forget the users that no longer share a channel with the bot.
+    fn left(&mut self, nick: &str, channel: &str, own_nick: &str) {
+        if nick.eq_ignore_ascii_case(own_nick) {
+            for user in self.users.values_mut() {
+                user.channels.retain(|c| c != channel);
+            }
+        } else if let Some(user) = self.users.get_mut(nick) {
+            user.channels.retain(|c| c != channel);
+        }
+        self.users.retain(|_, user| !user.channels.is_empty());
+    }
+    /// Whether the user going by `nick` matches `pattern`.
+    ///
+    /// Patterns with `!` or `@` are matched against the whole hostmask, others only against
+    /// the nick.
+    pub fn matches(&self, nick: &str, pattern: &str) -> bool {
+        if !pattern.contains('!') && !pattern.contains('@') {
+            return mask_matches(pattern, nick);
+        }
+        match self.users.get(&nick.to_lowercase()) {
+            Some(user) => mask_matches(pattern, &user.mask),
+            None => false,
+        }
+    }
+    /// The role of the user going by `nick`.
+    pub fn role(&self, nick: &str, roles: &Roles) -> Role {
+        let user = match self.users.get(&nick.to_lowercase()) {
+            Some(user) => user,
+            None => return Role::Everyone,
+        };
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|pattern| {
+                if pattern.starts_with("$a:") {
+                    match user.account {
+                        Some(ref account) => account.eq_ignore_ascii_case(&pattern[3..]),
+                        None => false,
+                    }
+                } else {
+                    mask_matches(pattern, &user.mask)
+                }
+            })
+        };
+        if matches(&roles.owner) {
+            Role::Owner
+        } else if matches(&roles.admin) {
+            Role::Admin
+        } else if matches(&roles.trusted) {
+            Role::Trusted
+        } else {
+            Role::Everyone
+        }
+    }
+}
+
+/// `*` means not logged in.
+fn parse_account(account: &str) -> Option<String> {
+    if account == "*" {
+        None
+    } else {
+        Some(account.to_owned())
+    }
+}
+
+/// Match `mask` against `pattern`, where `*` matches any number of characters, and `?` matches
+/// exactly one. Case insensitive.
+fn mask_matches(pattern: &str, mask: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let mask: Vec<char> = mask.to_lowercase().chars().collect();
+    let (mut p, mut m) = (0, 0);
+    // Where to continue from if the current attempt after a `*` fails
+    let mut backtrack = None;
+    while m < mask.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == mask[m]) {
+            p += 1;
+            m += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, m));
+            p += 1;
+        } else if let Some((star, star_m)) = backtrack {
+            p = star + 1;
+            m = star_m + 1;
+            backtrack = Some((star, star_m + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn test_mask_matches() {
+    assert!(mask_matches(
+        "*!*@staff.example.org",
+        "Boss!~boss@staff.example.org"
+    ));
+    assert!(mask_matches("boss!*@*", "Boss!~boss@somewhere"));
+    assert!(mask_matches("b?ss!*", "bass!x@y"));
+    assert!(!mask_matches("boss!*@*", "bossy!~boss@somewhere"));
+    assert!(!mask_matches("*!*@staff.example.org", "x!y@evil.org"));
+}
+
+#[test]
+fn test_account_reset() {
+    let roles = Roles {
+        owner: vec!["$a:boss".into()],
+        admin: Vec::new(),
+        trusted: Vec::new(),
+    };
+    let mut users = Users::default();
+    let mut observe = |line: &str| {
+        users.observe(&Message::parse(line).unwrap(), "bonca");
+        users.role("boss", &roles)
+    };
+    assert_eq!(
+        observe(":Boss!~boss@home JOIN #chan boss :The Boss"),
+        Role::Owner
+    );
+    // Boss quit unseen, and someone else took the nick
+    assert_eq!(
+        observe(":Boss!~evil@elsewhere PRIVMSG bonca :hi"),
+        Role::Everyone
+    );
+    assert_eq!(
+        observe(":Boss!~boss@home JOIN #chan boss :The Boss"),
+        Role::Owner
+    );
+    // Once Boss leaves, the bot wouldn't see them quit anymore
+    observe(":Boss!~boss@home PART #chan");
+    assert_eq!(
+        observe(":Boss!~boss@home PRIVMSG bonca :hi"),
+        Role::Everyone
+    );
+}
+
+#[test]
+fn test_who_reply() {
+    let roles = Roles {
+        owner: vec!["$a:boss".into()],
+        admin: vec!["$a:helper".into()],
+        trusted: Vec::new(),
+    };
+    let join = Message::parse(":bonca!~bonca@bot JOIN #chan").unwrap();
+    assert_eq!(
+        Users::who_query(&join, "Bonca")
+            .as_ref()
+            .map(String::as_str),
+        Some("WHO #chan %tcuhna,101")
+    );
+    let other = Message::parse(":Boss!~boss@home JOIN #chan").unwrap();
+    assert_eq!(Users::who_query(&other, "bonca"), None);
+    let mut users = Users::default();
+    for line in &[
+        ":irc.example.org 354 bonca 101 #chan ~boss home Boss boss",
+        ":irc.example.org 354 bonca 101 #chan ~guest there Guest 0",
+        // Not a reply to our query
+        ":irc.example.org 354 bonca 7 #chan ~help desk Helper helper",
+    ] {
+        users.observe(&Message::parse(line).unwrap(), "bonca");
+    }
+    assert_eq!(users.role("boss", &roles), Role::Owner);
+    assert_eq!(users.role("Guest", &roles), Role::Everyone);
+    assert_eq!(users.role("helper", &roles), Role::Everyone);
+    assert!(users.matches("guest", "*!~guest@there"));
+    // They are forgotten like the others when the bot leaves
+    users.observe(
+        &Message::parse(":bonca!~bonca@bot PART #chan").unwrap(),
+        "bonca",
+    );
+    assert_eq!(users.role("boss", &roles), Role::Everyone);
+}
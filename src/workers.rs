@@ -0,0 +1,363 @@
+//! Bounded pool of worker threads that plugin code runs on.
+
+use crate::config;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Job = Box<FnOnce() + Send>;
+
+/// How often the deadlines of the running jobs are checked.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) struct Workers {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signaled when a job gets queued, or a plugin becomes free.
+    queued: Condvar,
+    /// How many workers there should be.
+    threads: usize,
+    /// How many jobs of one plugin can be queued.
+    max_queue: usize,
+}
+
+struct State {
+    queue: VecDeque<Task>,
+    running: HashMap<u64, Running>,
+    /// The plugins that a job is running for, including the stuck ones.
+    ///
+    /// A plugin runs one job at a time, so the other jobs for it wait in the queue rather than
+    /// on a worker.
+    busy: Vec<String>,
+    next_id: u64,
+    /// How many worker threads exist, including the stuck ones.
+    live: usize,
+    /// How many workers are stuck in a job that's past its deadline.
+    stuck: usize,
+}
+
+struct Task {
+    description: String,
+    /// The plugins the job calls into.
+    plugins: Vec<String>,
+    job: Job,
+    /// When the job times out, counting from when it was queued, and what to do then.
+    deadline: Option<(Instant, Job)>,
+}
+
+struct Running {
+    description: String,
+    started: Instant,
+    deadline: Option<(Instant, Job)>,
+}
+
+impl Workers {
+    pub fn new(config: &config::Workers) -> Self {
+        let threads = config.threads.max(1);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                running: HashMap::new(),
+                busy: Vec::new(),
+                next_id: 0,
+                live: threads,
+                stuck: 0,
+            }),
+            queued: Condvar::new(),
+            threads,
+            max_queue: config.max_queue,
+        });
+        for _ in 0..threads {
+            spawn_worker(&shared);
+        }
+        thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || loop {
+                thread::sleep(WATCHDOG_INTERVAL);
+                check_deadlines(&shared);
+            }
+        });
+        Self { shared }
+    }
+    /// Run `job`, which calls into `plugins`, on a worker. `description` is shown in the status.
+    pub fn execute<F: FnOnce() + Send + 'static>(
+        &self,
+        description: String,
+        plugins: Vec<String>,
+        job: F,
+    ) {
+        self.push(Task {
+            description,
+            plugins,
+            job: Box::new(job),
+            deadline: None,
+        });
+    }
+    /// Run `job`, which calls into `plugins`, on a worker, and call `on_timeout` if it doesn't
+    /// finish within `timeout` from now.
+    ///
+    /// A job can't be stopped, so it keeps running after the timeout. Its worker is replaced
+    /// in the meantime, but only up to the size of the pool.
+    ///
+    /// Returns false if the job was dropped, because too many jobs are queued for the plugins.
+    pub fn execute_with_deadline<F, T>(
+        &self,
+        description: String,
+        plugins: Vec<String>,
+        timeout: Duration,
+        job: F,
+        on_timeout: T,
+    ) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+        T: FnOnce() + Send + 'static,
+    {
+        self.push(Task {
+            description,
+            plugins,
+            job: Box::new(job),
+            deadline: Some((Instant::now() + timeout, Box::new(on_timeout))),
+        })
+    }
+    /// Human readable description of the queued and running jobs.
+    pub fn status(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        let mut status = String::new();
+        writeln!(
+            &mut status,
+            "Workers: {} ({} stuck past their deadline)",
+            state.live, state.stuck
+        )
+        .unwrap();
+        writeln!(&mut status, "Queued jobs: {}", state.queue.len()).unwrap();
+        let mut running: Vec<&Running> = state.running.values().collect();
+        running.sort_by_key(|r| r.started);
+        for r in running {
+            writeln!(
+                &mut status,
+                "Running for {:.1}s: {}",
+                r.started.elapsed().as_secs_f64(),
+                r.description
+            )
+            .unwrap();
+        }
+        status
+    }
+    fn push(&self, task: Task) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let full = task.plugins.iter().any(|plugin| {
+            let queued = state
+                .queue
+                .iter()
+                .filter(|other| other.plugins.contains(plugin))
+                .count();
+            queued >= self.shared.max_queue
+        });
+        if full {
+            eprintln!("Worker queue is full, dropping job: {}", task.description);
+            return false;
+        }
+        state.queue.push_back(task);
+        self.shared.queued.notify_one();
+        true
+    }
+}
+
+impl State {
+    /// Take the first queued task whose plugins are all free.
+    fn take_runnable(&mut self) -> Option<Task> {
+        let busy = &self.busy;
+        let index = self
+            .queue
+            .iter()
+            .position(|task| !task.plugins.iter().any(|plugin| busy.contains(plugin)))?;
+        self.queue.remove(index)
+    }
+}
+
+fn spawn_worker(shared: &Arc<Shared>) {
+    let shared = Arc::clone(shared);
+    thread::spawn(move || work(&shared));
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let (id, plugins, job) = {
+            let mut state = shared.state.lock().unwrap();
+            let task = loop {
+                match state.take_runnable() {
+                    Some(task) => break task,
+                    None => state = shared.queued.wait(state).unwrap(),
+                }
+            };
+            let id = state.next_id;
+            state.next_id += 1;
+            state.busy.extend(task.plugins.iter().cloned());
+            state.running.insert(
+                id,
+                Running {
+                    description: task.description,
+                    started: Instant::now(),
+                    deadline: task.deadline,
+                },
+            );
+            (id, task.plugins, task.job)
+        };
+        // Not the plugins' panics, those are caught in the plugins. So this is our own copy.
+        let mut job = Some(job);
+        let result = plugin_api::catch_panic(&mut || job.take().unwrap()());
+        let mut state = shared.state.lock().unwrap();
+        for plugin in &plugins {
+            let index = state.busy.iter().position(|p| p == plugin).unwrap();
+            state.busy.swap_remove(index);
+        }
+        // Jobs may have been waiting for these plugins
+        shared.queued.notify_all();
+        let running = state.running.remove(&id);
+        if let Err(msg) = result {
+            let description = running
+                .as_ref()
+                .map_or("job past its deadline", |r| &r.description);
+            eprintln!("Job panicked: {}: {}", description, msg);
+        }
+        if running.is_none() {
+            // The job timed out, and the watchdog wrote this worker off as stuck
+            state.stuck -= 1;
+            if state.live - state.stuck > shared.threads {
+                // A replacement was started in the meantime, so we're not needed anymore
+                state.live -= 1;
+                return;
+            }
+        }
+    }
+}
+
+/// Notify about the jobs that are past their deadline, and replace their workers.
+fn check_deadlines(shared: &Arc<Shared>) {
+    let now = Instant::now();
+    let mut timeouts = Vec::new();
+    {
+        let mut state = shared.state.lock().unwrap();
+        let expired =
+            |deadline: &Option<(Instant, Job)>| deadline.as_ref().map_or(false, |d| d.0 <= now);
+        // The ones that didn't even get to start
+        let mut i = 0;
+        while i < state.queue.len() {
+            if expired(&state.queue[i].deadline) {
+                let task = state.queue.remove(i).unwrap();
+                eprintln!("Job timed out in the queue: {}", task.description);
+                timeouts.push(task.deadline.unwrap().1);
+            } else {
+                i += 1;
+            }
+        }
+        let stuck: Vec<u64> = state
+            .running
+            .iter()
+            .filter(|&(_, r)| expired(&r.deadline))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stuck {
+            let running = state.running.remove(&id).unwrap();
+            eprintln!("Job timed out: {}", running.description);
+            timeouts.push(running.deadline.unwrap().1);
+            state.stuck += 1;
+            // Don't let the stuck workers pile up endlessly
+            if state.live - state.stuck < shared.threads && state.live < 2 * shared.threads {
+                state.live += 1;
+                spawn_worker(shared);
+            }
+        }
+    }
+    for on_timeout in timeouts {
+        on_timeout();
+    }
+}
+
+#[test]
+fn test_deadline() {
+    use std::sync::mpsc;
+    let workers = Workers::new(&config::Workers {
+        threads: 1,
+        max_queue: 10,
+        command_timeout_secs: 0,
+    });
+    let (tx, rx) = mpsc::channel();
+    let tx2 = tx.clone();
+    workers.execute_with_deadline(
+        "slowpoke".into(),
+        vec!["slow".into()],
+        Duration::from_millis(10),
+        || thread::sleep(Duration::from_millis(500)),
+        move || tx2.send("timed out").unwrap(),
+    );
+    let tx3 = tx.clone();
+    workers.execute("quick".into(), vec!["quick".into()], move || {
+        tx.send("done").unwrap()
+    });
+    let timeout = Duration::from_secs(2);
+    assert_eq!(rx.recv_timeout(timeout), Ok("timed out"));
+    // The stuck worker was replaced, so the next job doesn't have to wait for it
+    assert_eq!(rx.recv_timeout(Duration::from_millis(300)), Ok("done"));
+    assert!(workers.status().contains("1 stuck"));
+    // A panic doesn't take the worker down with it
+    workers.execute("crashy".into(), vec![], || panic!("oh no"));
+    workers.execute("after".into(), vec![], move || tx3.send("after").unwrap());
+    assert_eq!(rx.recv_timeout(timeout), Ok("after"));
+    assert!(!workers.status().contains("crashy"));
+}
+
+#[test]
+fn test_busy_plugin() {
+    use std::sync::mpsc;
+    let workers = Workers::new(&config::Workers {
+        threads: 2,
+        max_queue: 1,
+        command_timeout_secs: 0,
+    });
+    let (tx, rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let started = tx.clone();
+    workers.execute("busy".into(), vec!["a".into()], move || {
+        started.send("started").unwrap();
+        done_rx.recv().unwrap();
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok("started"));
+    let tx2 = tx.clone();
+    let queued = workers.execute_with_deadline(
+        "waiting".into(),
+        vec!["a".into()],
+        Duration::from_millis(50),
+        move || tx2.send("ran").unwrap(),
+        {
+            let tx = tx.clone();
+            move || tx.send("timed out").unwrap()
+        },
+    );
+    assert!(queued);
+    // Too many jobs for the busy plugin
+    assert!(!workers.execute_with_deadline(
+        "dropped".into(),
+        vec!["a".into()],
+        Duration::from_secs(10),
+        || (),
+        || (),
+    ));
+    // The other worker isn't waiting for the busy plugin, so the others aren't held up
+    for _ in 0..3 {
+        let tx = tx.clone();
+        workers.execute("other".into(), vec!["b".into()], move || {
+            tx.send("other").unwrap()
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok("other"));
+    }
+    // The deadline counts from when the job was queued
+    assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok("timed out"));
+    done_tx.send(()).unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+}
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Regenerates the certificates that the TLS tests use: a self-signed CA,
+# and a certificate for localhost signed by it.
+set -e
+cd "$(dirname "$0")"
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 -subj "/CN=BoncaRobot test CA" \
+    -keyout ca.key -out ca.pem
+openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" -keyout localhost.key -out localhost.csr
+printf "subjectAltName=DNS:localhost,IP:127.0.0.1\n" > localhost.ext
+openssl x509 -req -days 36500 -in localhost.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+    -extfile localhost.ext -out localhost.pem
+openssl pkcs12 -export -passout pass:test -certpbe PBE-SHA1-3DES -keypbe PBE-SHA1-3DES \
+    -macalg sha1 -inkey localhost.key -in localhost.pem -certfile ca.pem -out localhost.p12
+rm ca.key ca.srl localhost.key localhost.csr localhost.ext localhost.pem